@@ -0,0 +1,213 @@
+use crate::flags;
+use multi_key_db::collation::Collation;
+use multi_key_db::database::Database;
+use multi_key_db::key::Key;
+use multi_key_db::node::NodeType;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::collections::BTreeMap;
+
+/// Tab completion for interactive mode.
+/// The first word completes to a command name, every other word
+/// completes one key segment at a time from the database.
+pub struct KeyCompleter {
+    commands: Vec<String>,
+    /// Keys by their text folded under the collation of the database.
+    keys: BTreeMap<String, String>,
+    divider: char,
+    collation: Collation,
+}
+
+impl KeyCompleter {
+    pub fn new() -> KeyCompleter {
+        let mut commands: Vec<String> = flags::generate_interactive_app()
+            .get_subcommands()
+            .map(|command| command.get_name().to_string())
+            .collect();
+        commands.push("help".into());
+        commands.sort();
+        KeyCompleter {
+            commands,
+            keys: BTreeMap::new(),
+            divider: '.',
+            collation: Collation::default(),
+        }
+    }
+
+    /// Takes a fresh snapshot of the keys below current, or of the whole database.
+    /// Parent keys are stored with a trailing divider.
+    pub fn refresh(&mut self, database: &Database<String, String>, current: Option<&Key<String>>) {
+        self.keys.clear();
+        // Parents below the root do not know the divider of the file
+        self.divider = database.divider();
+        self.collation = database.collation();
+        let mut keys = Vec::new();
+        match current {
+            Some(key) => {
+                if let Some(NodeType::Parent(parent)) = database.get_node(key) {
+                    collect_keys(parent, "", self.divider, &mut keys);
+                }
+            }
+            None => collect_keys(database, "", self.divider, &mut keys),
+        }
+        for key in keys {
+            self.keys.insert(self.collation.fold(&key), key);
+        }
+    }
+
+    fn complete_word(&self, word: &str, first: bool) -> Vec<String> {
+        if first {
+            return self
+                .commands
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .cloned()
+                .collect();
+        }
+        // Typed text matches keys the way the database finds them
+        let word = self.collation.fold(word);
+        self.keys
+            .range(word.clone()..)
+            .take_while(|(folded, _)| folded.starts_with(&word))
+            .filter(|(folded, _)| {
+                // Only complete up to the end of the current segment
+                let rest = &folded[word.len()..];
+                match rest.find(self.divider) {
+                    Some(index) => index + self.divider.len_utf8() == rest.len(),
+                    None => !rest.is_empty() || !word.ends_with(self.divider),
+                }
+            })
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+}
+
+fn collect_keys(
+    database: &Database<String, String>,
+    prefix: &str,
+    divider: char,
+    keys: &mut Vec<String>,
+) {
+    for (segment, node) in &database.map {
+        let key = format!("{}{}", prefix, segment.to_string(divider));
+        match node {
+            NodeType::Parent(parent) => {
+                let parent_key = format!("{}{}", key, divider);
+                collect_keys(parent, &parent_key, divider, keys);
+                keys.push(parent_key);
+            }
+            NodeType::Value(_) => {
+                keys.push(key);
+            }
+        }
+    }
+}
+
+impl Completer for KeyCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |index| index + 1);
+        let first = line[..start].trim().is_empty();
+        Ok((start, self.complete_word(&line[start..pos], first)))
+    }
+}
+
+impl Hinter for KeyCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for KeyCompleter {}
+
+impl Validator for KeyCompleter {}
+
+impl Helper for KeyCompleter {}
+
+#[cfg(test)]
+mod tests {
+    use crate::completion::*;
+
+    fn completer() -> KeyCompleter {
+        let mut database = Database::new();
+        for key in ["work.team.git", "work.team.lead", "work.name", "home"] {
+            database
+                .insert(Key::new_from_str(key, '.').unwrap(), "value".into())
+                .unwrap();
+        }
+        let mut completer = KeyCompleter::new();
+        completer.refresh(&database, None);
+        completer
+    }
+
+    #[test]
+    fn complete_segments() {
+        let completer = completer();
+        assert_eq!(
+            vec!["work.team."],
+            completer.complete_word("work.te", false)
+        );
+        assert_eq!(
+            vec!["work.name", "work.team."],
+            completer.complete_word("work.", false)
+        );
+        assert_eq!(vec!["home", "work."], completer.complete_word("", false));
+        assert!(completer.complete_word("missing", false).is_empty());
+    }
+
+    #[test]
+    fn complete_relative_to_current() {
+        let mut database = Database::new();
+        database
+            .insert(Key::new_from_str("work.team.git", '.').unwrap(), "v".into())
+            .unwrap();
+        let mut completer = KeyCompleter::new();
+        completer.refresh(&database, Some(&Key::new_from_str("work", '.').unwrap()));
+        assert_eq!(vec!["team."], completer.complete_word("t", false));
+    }
+
+    #[test]
+    fn complete_with_divider() {
+        let contents = "split:/\nwork/team/git\tv\nwork/name\tv\n";
+        let database = Database::new_from_file(&mut contents.as_bytes()).unwrap();
+        let mut completer = KeyCompleter::new();
+        completer.refresh(&database, None);
+        assert_eq!(
+            vec!["work/team/"],
+            completer.complete_word("work/te", false)
+        );
+        assert_eq!(
+            vec!["work/name", "work/team/"],
+            completer.complete_word("work/", false)
+        );
+    }
+
+    #[test]
+    fn complete_through_collation() {
+        let contents = "collation:case-folding\nApp.x\tv\nApp.Yard\tv\n";
+        let database = Database::new_from_file(&mut contents.as_bytes()).unwrap();
+        let mut completer = KeyCompleter::new();
+        completer.refresh(&database, None);
+        assert_eq!(vec!["App."], completer.complete_word("app", false));
+        assert_eq!(vec!["App.Yard"], completer.complete_word("app.y", false));
+    }
+
+    #[test]
+    fn complete_commands() {
+        let completer = completer();
+        assert_eq!(
+            vec!["save", "search", "secret"],
+            completer.complete_word("s", true)
+        );
+        assert_eq!(vec!["remove"], completer.complete_word("rem", true));
+    }
+}
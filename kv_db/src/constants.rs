@@ -6,25 +6,122 @@ pub(crate) const REMOVE: &str = "remove";
 pub(crate) const PRINT: &str = "print";
 pub(crate) const LOG: &str = "log";
 pub(crate) const SAVE: &str = "save";
+pub(crate) const EXIT: &str = "exit";
+pub(crate) const CD: &str = "cd";
+pub(crate) const PWD: &str = "pwd";
+pub(crate) const UNDO: &str = "undo";
+pub(crate) const REDO: &str = "redo";
+pub(crate) const CHANGES: &str = "changes";
+pub(crate) const PATH: &str = "path";
+pub(crate) const TREE: &str = "tree";
+pub(crate) const LS: &str = "ls";
+pub(crate) const SEARCH: &str = "search";
+pub(crate) const EXEC: &str = "exec";
+pub(crate) const EDIT: &str = "edit";
+pub(crate) const DIFF: &str = "diff";
+pub(crate) const MERGE_DRIVER: &str = "merge-driver";
+pub(crate) const PATCH: &str = "patch";
+pub(crate) const CREATE: &str = "create";
+pub(crate) const APPLY: &str = "apply";
+pub(crate) const ENCRYPT: &str = "encrypt";
+pub(crate) const DECRYPT: &str = "decrypt";
+pub(crate) const SECRET: &str = "secret";
+pub(crate) const COLLATE: &str = "collate";
+pub(crate) const KEYGEN: &str = "keygen";
+pub(crate) const VERIFY: &str = "verify";
+pub(crate) const FSCK: &str = "fsck";
 //commands
 pub(crate) const INTERACTIVE: &str = "interactive";
 pub(crate) const FILE: &str = "db-file";
+pub(crate) const ENCRYPTED: &str = "encrypted";
+pub(crate) const LOAD_MODE: &str = "load-mode";
+pub(crate) const DROP_SKIPPED: &str = "drop-skipped";
+pub(crate) const KEY_ORDER: &str = "key-order";
+pub(crate) const COLLATION: &str = "collation";
+pub(crate) const PASSPHRASE_VARIABLE: &str = "KV_DB_PASSPHRASE";
+pub(crate) const SIGN_KEY: &str = "sign-key";
+pub(crate) const VERIFY_KEY: &str = "verify-key";
+pub(crate) const SIGNATURE_EXTENSION: &str = ".sig";
+pub(crate) const LOCK_EXTENSION: &str = ".lock";
+//fsck options
+pub(crate) const REPAIR: &str = "repair";
+pub(crate) const REPAIRED_EXTENSION: &str = ".repaired";
+pub(crate) const REJECTED_EXTENSION: &str = ".rejected";
+//keygen options
+pub(crate) const NAME: &str = "name";
+//add options
+pub(crate) const IF_ABSENT: &str = "if-absent";
+pub(crate) const IF_EQUALS: &str = "if-equals";
+pub(crate) const STDIN: &str = "stdin";
+//secret options
+pub(crate) const UNMARK: &str = "unmark";
+pub(crate) const REVEAL: &str = "reveal";
+pub(crate) const SECRET_MASK: &str = "********";
+//tree options
+pub(crate) const DEPTH: &str = "depth";
+//ls options
+pub(crate) const LONG: &str = "long";
+pub(crate) const PREVIEW_LENGTH: usize = 40;
+//search options
+pub(crate) const PATTERN: &str = "pattern";
+pub(crate) const KEYS: &str = "keys";
+pub(crate) const VALUES: &str = "values";
+pub(crate) const HIGHLIGHT_START: &str = "\x1b[1;31m";
+pub(crate) const HIGHLIGHT_END: &str = "\x1b[0m";
+//diff options
+pub(crate) const FILES: &str = "files";
+pub(crate) const DIFF_PARENT: &str = "(parent)";
+//patch options
+pub(crate) const PATCH_FILE: &str = "patch-file";
+pub(crate) const DRY_RUN: &str = "dry-run";
+//merge-driver options
+pub(crate) const BASE: &str = "base";
+pub(crate) const OURS: &str = "ours";
+pub(crate) const THEIRS: &str = "theirs";
+pub(crate) const STRATEGY: &str = "strategy";
+//exec options
+pub(crate) const SCRIPT: &str = "script";
+pub(crate) const KEEP_GOING: &str = "keep-going";
+//output options
+pub(crate) const RAW: &str = "raw";
+pub(crate) const NULL: &str = "null";
+pub(crate) const OUTPUT: &str = "output";
+//exit codes
+pub(crate) const EXIT_IO_ERROR: i32 = 1;
+// 2 is left to clap, which exits with it on usage errors
+pub(crate) const EXIT_PARSE_ERROR: i32 = 3;
+pub(crate) const EXIT_KEY_NOT_FOUND: i32 = 4;
+pub(crate) const EXIT_CONFLICT: i32 = 5;
+pub(crate) const EXIT_CONDITION_FAILED: i32 = 6;
+pub(crate) const EXIT_MERGE_CONFLICT: i32 = 7;
+pub(crate) const EXIT_ENCRYPTION: i32 = 8;
+pub(crate) const EXIT_VERIFICATION: i32 = 9;
 
 pub fn retrieve_db_file() -> String {
-    let db_file: String = ".kv.db".into();
+    retrieve_data_file(".kv.db")
+}
+
+pub fn retrieve_history_file() -> String {
+    retrieve_data_file(".kv_history")
+}
+
+/// Path of file_name in the KV_DB data directory,
+/// or just file_name when there is no data directory.
+fn retrieve_data_file(file_name: &str) -> String {
+    let data_file: String = file_name.into();
 
     match data_dir() {
         Some(mut path) => {
             path.push("KV_DB");
             if std::fs::create_dir_all(&path).is_err() {
-                return db_file;
+                return data_file;
             }
-            path.push(&db_file);
+            path.push(&data_file);
             match path.to_str() {
                 Some(path_str) => path_str.into(),
-                None => db_file,
+                None => data_file,
             }
         }
-        None => db_file,
+        None => data_file,
     }
 }
@@ -0,0 +1,177 @@
+use crate::journal::{self, Change};
+use log::{debug, warn};
+use multi_key_db::database::Database;
+use multi_key_db::error::DBError;
+use multi_key_db::key::Key;
+use multi_key_db::node::NodeType;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use tempfile::Builder;
+
+/// Opens the values below prefix, or the whole database, in $EDITOR and
+/// applies whatever was changed. Keys in the file are relative to prefix.
+/// A file that does not parse or whose changes do not apply is reported and
+/// opened again, leaving the editor with an error exit code gives up
+/// without changing anything.
+pub fn edit(
+    database: &mut Database<String, String>,
+    prefix: Option<Key<String>>,
+) -> Result<Vec<Change>, DBError> {
+    let original = subtree(database, prefix.as_ref())?;
+    // A new file only the user can read, the values may be secret
+    let mut file = Builder::new()
+        .prefix("kv_db-edit-")
+        .suffix(".tsv")
+        .tempfile()?;
+    if let Some(prefix) = &prefix {
+        writeln!(
+            file,
+            "# Keys below {}",
+            prefix.to_string(database.divider())
+        )?;
+    }
+    original.export(&mut file)?;
+
+    let result = edit_until_applied(database, &original, prefix.as_ref(), file.path());
+    if let Err(error) = file.close() {
+        warn!("Temporary edit file not removed: {}", error);
+    }
+    let (edited, changes) = result?;
+    *database = edited;
+    Ok(changes)
+}
+
+/// The database with the changes of the edited file, applied to a copy so
+/// a change that fails leaves database as it was.
+fn apply(
+    database: &Database<String, String>,
+    original: &Database<String, String>,
+    edited: &Database<String, String>,
+    prefix: Option<&Key<String>>,
+) -> Result<(Database<String, String>, Vec<Change>), DBError> {
+    let changes = journal::changes(original, edited)
+        .into_iter()
+        .map(|change| Change {
+            key: full_key(prefix, &change.key),
+            ..change
+        })
+        .collect();
+    let mut copy = database.clone();
+    let changes = journal::apply_all(changes, &mut copy)?;
+    Ok((copy, changes))
+}
+
+/// Copy of the values below prefix with keys relative to it. The copy
+/// keeps the divider and collation, its file header carries them into the
+/// edited file.
+fn subtree(
+    database: &Database<String, String>,
+    prefix: Option<&Key<String>>,
+) -> Result<Database<String, String>, DBError> {
+    let mut copy = database.nested();
+    let parent = match prefix {
+        None => database,
+        Some(prefix) => match database.get_node(prefix) {
+            Some(NodeType::Parent(parent)) => parent,
+            Some(NodeType::Value(_)) => {
+                return Err(DBError::MultiKeyExtendValueKey {
+                    key: prefix.clone(),
+                    existing: prefix.clone(),
+                    divider: database.divider(),
+                    line: None,
+                })
+            }
+            // Editing a new group
+            None => return Ok(copy),
+        },
+    };
+    for (key, value) in parent.generate_key_value_pairs() {
+        copy.insert(key, value.clone())?;
+    }
+    Ok(copy)
+}
+
+fn full_key(prefix: Option<&Key<String>>, key: &Key<String>) -> Key<String> {
+    match prefix {
+        Some(prefix) => {
+            let mut full = prefix.clone();
+            full.add(key);
+            full
+        }
+        None => key.clone(),
+    }
+}
+
+fn edit_until_applied(
+    database: &Database<String, String>,
+    original: &Database<String, String>,
+    prefix: Option<&Key<String>>,
+    path: &Path,
+) -> Result<(Database<String, String>, Vec<Change>), DBError> {
+    loop {
+        run_editor(path)?;
+        let result = Database::new_from_file(&mut File::open(path)?)
+            .and_then(|edited| apply(database, original, &edited, prefix));
+        match result {
+            Ok(applied) => return Ok(applied),
+            Err(DBError::IOError(error)) => return Err(DBError::IOError(error)),
+            Err(error) => {
+                eprint!("{}", error);
+                eprintln!("Opening the editor again, exit it with an error to give up.");
+            }
+        }
+    }
+}
+
+/// $EDITOR, falling back to vi. The variable may hold arguments.
+fn run_editor(path: &Path) -> Result<(), DBError> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".into());
+    debug!("Editor: {}", editor);
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program).args(words).arg(path).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("Editor exited with {}", status)).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::edit::*;
+
+    #[test]
+    fn subtree_is_relative() {
+        let mut database = Database::new();
+        for (key, value) in [
+            ("app.prod.host", "a"),
+            ("app.prod.port", "1"),
+            ("app.dev", "b"),
+        ] {
+            database
+                .insert(Key::new_from_str(key, '.').unwrap(), value.into())
+                .unwrap();
+        }
+        let prefix = Key::new_from_str("app.prod", '.').unwrap();
+
+        let copy = subtree(&database, Some(&prefix)).unwrap();
+        let mut contents = Vec::new();
+        copy.export(&mut contents).unwrap();
+        assert_eq!("host\ta\nport\t1\n", String::from_utf8(contents).unwrap());
+
+        let missing = Key::new_from_str("app.test", '.').unwrap();
+        assert!(subtree(&database, Some(&missing)).unwrap().is_empty());
+        let value = Key::new_from_str("app.dev", '.').unwrap();
+        assert_eq!(
+            DBError::MultiKeyExtendValueKey {
+                key: value.clone(),
+                existing: value.clone(),
+                divider: '.',
+                line: None,
+            },
+            subtree(&database, Some(&value)).err().unwrap()
+        );
+    }
+}
@@ -1,141 +1,1111 @@
+use crate::completion::KeyCompleter;
 use crate::constants::{self};
-use std::fs::OpenOptions;
+use crate::edit;
+use crate::flags;
+use crate::journal::{self, Journal};
+use crate::storage::DbFile;
+use crate::tokenizer::{expand_shorthand, tokenize};
+use std::fs::File;
 
-use crate::Key;
-use crate::KeyError;
-use log::trace;
+use clap::ArgMatches;
+use log::{trace, warn};
+use multi_key_db::collation::Collation;
 use multi_key_db::database::Database;
-use multi_key_db::error::DBError;
-use std::io::{self, Write};
+use multi_key_db::diff::Change as Difference;
+use multi_key_db::error::{DBError, KeyError};
+use multi_key_db::key::Key;
+use multi_key_db::node::NodeType;
+use multi_key_db::patch::{Operation, Patch};
+use multi_key_db::render::{Entry, OutputFormat, Renderer, TreeRenderer};
+use multi_key_db::search::{SearchPattern, SearchScope};
+use regex::Regex;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::str::FromStr;
 
-pub fn event_loop(database: &mut Database<String, String>, db_file: &str) -> Result<(), DBError> {
+/// True when the session ended with changes that were not saved.
+pub fn event_loop(
+    database: &mut Database<String, String>,
+    db_file: &DbFile,
+) -> Result<bool, DBError> {
     io::stdout().write_all(b"Support Commands:\n")?;
-    io::stdout().write_all(b"Add    -k <key>... -v <value>...\n")?;
-    io::stdout().write_all(b"Get    -k <key>...\n")?;
-    io::stdout().write_all(b"Remove -k <key>...\n")?;
-    io::stdout().write_all(b"Print\n")?;
-    io::stdout().write_all(b"Save to Disk\n")?;
-    io::stdout().write_all(b"Exit\n")?;
-    loop {
-        io::stdout().write_all(b"[kv db]")?;
-        io::stdout().flush()?;
+    io::stdout().write_all(b"add    -k <key> -v <value>, or add <key>=<value>\n")?;
+    io::stdout().write_all(b"get    -k <key>\n")?;
+    io::stdout().write_all(b"remove -k <key>\n")?;
+    io::stdout().write_all(b"print, tree, ls, search, edit\n")?;
+    io::stdout().write_all(b"cd     <key>, .. or .<absolute key>, and pwd\n")?;
+    io::stdout().write_all(b"undo, redo and changes since the last save\n")?;
+    io::stdout().write_all(b"save   Save to Disk\n")?;
+    io::stdout().write_all(b"exit\n")?;
+    io::stdout().write_all(b"help   Show every command and option\n")?;
 
-        let mut buffer = String::new();
-        io::stdin().read_line(&mut buffer)?;
+    let mut editor: Editor<KeyCompleter, DefaultHistory> = Editor::new().map_err(readline_error)?;
+    editor.set_helper(Some(KeyCompleter::new()));
+    // Piped input is not typed by hand, keep it out of the history
+    let history_file = if io::stdin().is_terminal() {
+        Some(constants::retrieve_history_file())
+    } else {
+        None
+    };
+    if let Some(history_file) = &history_file {
+        if editor.load_history(history_file).is_err() {
+            trace!("No history loaded from {}", history_file);
+        }
+    }
+
+    let result = read_commands(database, db_file, &mut editor);
+    if let Some(history_file) = &history_file {
+        if let Err(error) = editor.save_history(history_file) {
+            warn!("History writing to disk failure: {}", error);
+        }
+    }
+    result
+}
 
-        if buffer.is_empty() {
+fn read_commands(
+    database: &mut Database<String, String>,
+    db_file: &DbFile,
+    editor: &mut Editor<KeyCompleter, DefaultHistory>,
+) -> Result<bool, DBError> {
+    let mut session = Session::new(db_file);
+    loop {
+        if let Some(completer) = editor.helper_mut() {
+            completer.refresh(database, session.current.as_ref());
+        }
+        let prompt = match &session.current {
+            Some(key) => format!("[kv db {}]", key.to_string(database.divider())),
+            None => "[kv db]".into(),
+        };
+        let buffer = match editor.readline(&prompt) {
+            Ok(buffer) => buffer,
+            Err(ReadlineError::Interrupted) => continue,
+            // End of input, behave like exit
+            Err(ReadlineError::Eof) => return Ok(session.changed),
+            Err(error) => return Err(readline_error(error)),
+        };
+        if buffer.trim().is_empty() {
             no_command_found();
             continue;
         }
+        editor
+            .add_history_entry(buffer.as_str())
+            .map_err(readline_error)?;
 
-        let split: Vec<&str> = buffer.split(' ').collect();
-        if split.is_empty() {
-            no_command_found();
+        match run_line(database, &mut session, &buffer) {
+            Ok(Flow::Continue) => (),
+            Ok(Flow::Exit) => return Ok(session.changed),
+            Ok(Flow::Save) => match db_file.save(database) {
+                Ok(()) => session.changed = false,
+                Err(error) => eprintln!("Database writing to disk failure: {}", error),
+            },
+            Err(error) => eprint!("{}", error),
+        }
+    }
+}
+
+/// Runs every line of a script with the interactive command set.
+/// Blank lines and lines starting with # are skipped, save is left to the
+/// caller so the whole script is written at once.
+/// Stops at the first failing line, unless keep_going is set, in which case
+/// the first failure is kept in the end after the last line has run.
+pub fn run_script<R>(
+    database: &mut Database<String, String>,
+    db_file: &DbFile,
+    reader: R,
+    keep_going: bool,
+) -> Result<ScriptEnd, DBError>
+where
+    R: BufRead,
+{
+    let mut session = Session::new(db_file);
+    let mut first_error = None;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
-        match split[0].trim() {
-            constants::ADD => {
-                if split.len() != 5 || split[1].trim() != "-k" || split[3] != "-v" {
-                    eprintln!("Add requires -k <key> -v <value>");
-                    continue;
+        match run_line(database, &mut session, &line) {
+            Ok(Flow::Exit) => break,
+            Ok(_) => (),
+            Err(error) => {
+                eprint!("line {}: {}", index + 1, error);
+                let error = error.into_db_error();
+                if !keep_going {
+                    return Err(error);
                 }
-                let multi_key = create_key(split[2].trim())?;
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    Ok(ScriptEnd {
+        error: first_error,
+        changed: session.changed,
+    })
+}
 
-                let mut value = split[4];
-                while value.ends_with('\n') || value.ends_with('\r') {
-                    value = value.trim_end_matches('\n');
-                    value = value.trim_end_matches('\r');
-                }
+/// How a script that ran to its last line ended.
+pub struct ScriptEnd {
+    /// The first failing line, with keep_going
+    pub error: Option<DBError>,
+    /// True when a line ran a command that changes the database
+    pub changed: bool,
+}
 
-                add(database, multi_key, value.to_string())?
-            }
-            constants::GET => {
-                if split.len() != 3 || split[1].trim() != "-k" {
-                    eprintln!("Get requires -k <key> ");
-                    continue;
-                }
-                let multi_key = create_key(split[2].trim())?;
+/// State kept between the lines of a session.
+struct Session {
+    /// Relative keys are resolved against the current key
+    current: Option<Key<String>>,
+    journal: Journal,
+    /// Compared against for changes
+    db_file: DbFile,
+    /// True once a command changed the database since the last save
+    changed: bool,
+}
 
-                get(database, multi_key)?
+impl Session {
+    fn new(db_file: &DbFile) -> Session {
+        Session {
+            current: None,
+            journal: Journal::default(),
+            db_file: db_file.clone(),
+            changed: false,
+        }
+    }
+}
+
+/// What the caller should do after a line has run.
+enum Flow {
+    Continue,
+    Save,
+    Exit,
+}
+
+/// Why a line failed, parse errors keep the message of the parser.
+enum LineError {
+    Parse(String),
+    Database(DBError),
+}
+
+impl LineError {
+    fn into_db_error(self) -> DBError {
+        match self {
+            LineError::Parse(_) => KeyError::ParseError.into(),
+            LineError::Database(error) => error,
+        }
+    }
+}
+
+impl From<DBError> for LineError {
+    fn from(error: DBError) -> LineError {
+        LineError::Database(error)
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineError::Parse(message) => writeln!(f, "{}", message.trim_end()),
+            LineError::Database(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Splits a line into arguments and runs the command.
+fn run_line(
+    database: &mut Database<String, String>,
+    session: &mut Session,
+    line: &str,
+) -> Result<Flow, LineError> {
+    let tokens = tokenize(line).map_err(|error| LineError::Parse(error.to_string()))?;
+    if tokens.is_empty() {
+        return Ok(Flow::Continue);
+    }
+
+    let matches =
+        match flags::generate_interactive_app().try_get_matches_from(expand_shorthand(tokens)) {
+            Ok(matches) => matches,
+            // Help is reported as an error by clap
+            Err(error) if error.kind == clap::ErrorKind::DisplayHelp => {
+                println!("{}", error);
+                return Ok(Flow::Continue);
             }
-            constants::PRINT => flush_to_stdout(database)?,
-            constants::REMOVE => {
-                if split.len() != 3 || split[1].trim() != "-k" {
-                    eprintln!("Remove requires -k <key> ");
-                    continue;
-                }
-                let multi_key = create_key(split[2].trim())?;
-                remove(database, multi_key)?
+            Err(error) => return Err(LineError::Parse(error.to_string())),
+        };
+    match matches.subcommand() {
+        Some((constants::EXIT, _)) => return Ok(Flow::Exit),
+        Some((constants::SAVE, _)) => return Ok(Flow::Save),
+        Some((constants::CD, command)) => {
+            session.current = change_key(
+                database,
+                session.current.as_ref(),
+                command.value_of(constants::PATH),
+            )?;
+        }
+        Some((constants::PWD, _)) => match &session.current {
+            Some(key) => println!("{}", key.to_string(database.divider())),
+            None => println!("."),
+        },
+        Some((constants::UNDO, _)) => match session.journal.undo(database)? {
+            Some(changes) => {
+                session.changed = true;
+                describe(database, &changes, false)
             }
-            "exit" => {
-                return Ok(());
+            None => eprintln!("Nothing to undo"),
+        },
+        Some((constants::REDO, _)) => match session.journal.redo(database)? {
+            Some(changes) => {
+                session.changed = true;
+                describe(database, &changes, false)
             }
-            constants::SAVE => {
-                if let Err(error) = save_to_disk(database, db_file) {
-                    eprintln!("Database writing to disk failure: {}", error);
-                }
+            None => eprintln!("Nothing to redo"),
+        },
+        Some((constants::CHANGES, command)) => {
+            let saved = session.db_file.load()?;
+            let changes = journal::changes(&saved, database);
+            describe(database, &changes, command.is_present(constants::REVEAL));
+        }
+        Some((constants::ADD, command)) if reads_stdin(command) => {
+            return Err(LineError::Parse(
+                "Standard input belongs to the session, add the values of a file with @file".into(),
+            ))
+        }
+        Some((name, command)) => {
+            // Commands that change values are recorded as the difference
+            // they made, so they can be undone
+            let before = mutates(name, command)
+                .then(|| Before::take(database, name, command, session.current.as_ref()));
+            let result = execute(
+                database,
+                &session.db_file,
+                name,
+                command,
+                session.current.as_ref(),
+            );
+            if let Some(before) = before {
+                session.journal.record(before.changes(database));
+                session.changed |= result.is_ok();
             }
-            unsupported => {
-                eprintln!("Unsupported command {}", unsupported);
+            result?;
+        }
+        None => no_command_found(),
+    }
+    Ok(Flow::Continue)
+}
+
+/// What a command that changes values is compared against once it ran.
+enum Before {
+    /// The value of the only key the command can change, None without one
+    Value(Key<String>, Option<String>),
+    /// A copy of the whole database, for commands changing several keys
+    Database(Database<String, String>),
+}
+
+impl Before {
+    fn take(
+        database: &Database<String, String>,
+        name: &str,
+        command: &ArgMatches,
+        current: Option<&Key<String>>,
+    ) -> Before {
+        let single = match name {
+            constants::ADD => !command.is_present(constants::STDIN),
+            constants::REMOVE => true,
+            _ => false,
+        };
+        match single.then(|| retrieve_key(command, database.divider(), current)) {
+            Some(Ok(key)) => {
+                let key = database.stored(&key);
+                let value = value_at(database, &key);
+                Before::Value(key, value)
+            }
+            _ => Before::Database(database.clone()),
+        }
+    }
+
+    fn changes(self, database: &Database<String, String>) -> Vec<journal::Change> {
+        match self {
+            Before::Value(key, before) => {
+                let after = value_at(database, &key);
+                if before == after {
+                    return Vec::new();
+                }
+                vec![journal::Change { key, before, after }]
             }
+            Before::Database(before) => journal::changes(&before, database),
         }
     }
 }
 
+/// The value stored at key itself, unlike get not that of a parent.
+fn value_at(database: &Database<String, String>, key: &Key<String>) -> Option<String> {
+    database
+        .get_node(key)
+        .and_then(NodeType::get_value_ref)
+        .cloned()
+}
+
+/// True when add would read standard input, with --stdin or a value of -.
+fn reads_stdin(command: &ArgMatches) -> bool {
+    command.is_present(constants::STDIN) || command.value_of("value") == Some("-")
+}
+
+/// True for the commands that can change the database, only those are saved.
+pub fn mutates(name: &str, command: &ArgMatches) -> bool {
+    match name {
+        constants::ADD | constants::REMOVE | constants::EDIT | constants::COLLATE => true,
+        // Without a key secret lists the secret keys
+        constants::SECRET => command.is_present("key"),
+        constants::PATCH => matches!(
+            command.subcommand(),
+            Some((constants::APPLY, apply)) if !apply.is_present(constants::DRY_RUN)
+        ),
+        _ => false,
+    }
+}
+
+/// Prints the changes, the values of secret keys masked unless reveal.
+fn describe(database: &Database<String, String>, changes: &[journal::Change], reveal: bool) {
+    for change in changes {
+        let change = if !reveal && database.is_secret(&change.key) {
+            Cow::Owned(change.masked(constants::SECRET_MASK))
+        } else {
+            Cow::Borrowed(change)
+        };
+        print!("{}", change.describe(database.divider()));
+    }
+}
+
+/// Works out the current key after cd.
+/// No path goes back to the root, `..` goes up one level, and any other
+/// path has to be a parent key.
+fn change_key(
+    database: &Database<String, String>,
+    current: Option<&Key<String>>,
+    path: Option<&str>,
+) -> Result<Option<Key<String>>, DBError> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    if path == ".." {
+        return Ok(current.and_then(|key| key.parent().ok()));
+    }
+    let segments = path.split(database.divider()).map(String::from).collect();
+    let key = resolve_key(segments, current)?;
+    match database.get_node(&key) {
+        Some(NodeType::Parent(_)) => Ok(Some(key)),
+        // Values can not be moved into
+        _ => Err(DBError::KeyNotFound),
+    }
+}
+
+fn readline_error(error: ReadlineError) -> DBError {
+    match error {
+        ReadlineError::Io(error) => DBError::IOError(error),
+        error => DBError::IOError(io::Error::other(error)),
+    }
+}
+
 fn no_command_found() {
     eprintln!("No Command Entered");
 }
 
-fn create_key(key: &str) -> Result<Key<String>, KeyError> {
-    Key::new_from_str(key, '.')
+/// Runs one of the database subcommands shared by the command line and interactive mode.
+/// Keys are resolved against current, see resolve_key. Other files are read
+/// with the settings of db_file.
+pub fn execute(
+    database: &mut Database<String, String>,
+    db_file: &DbFile,
+    name: &str,
+    command: &ArgMatches,
+    current: Option<&Key<String>>,
+) -> Result<(), DBError> {
+    match name {
+        constants::ADD => {
+            let condition = if command.is_present(constants::IF_ABSENT) {
+                AddCondition::IfAbsent
+            } else if let Some(old) = command.value_of(constants::IF_EQUALS) {
+                AddCondition::IfEquals(old.to_string())
+            } else {
+                AddCondition::Always
+            };
+            if command.is_present(constants::STDIN) {
+                return add_all(database, &mut io::stdin(), current, condition);
+            }
+            let key = retrieve_key(command, database.divider(), current)?;
+            let value = command.value_of("value").ok_or(KeyError::ParseError)?;
+            let value = read_value(value)?;
+            add(database, key, value, condition)
+        }
+        constants::GET => get(
+            &visible(database, command),
+            retrieve_key(command, database.divider(), current)?,
+            retrieve_output(command),
+        ),
+        constants::REMOVE => remove(
+            database,
+            retrieve_key(command, database.divider(), current)?,
+        ),
+        constants::PRINT => flush_to_stdout(&visible(database, command), retrieve_output(command)),
+        constants::TREE => {
+            let prefix = retrieve_prefix(command, database.divider(), current)?;
+            let depth = match command.value_of(constants::DEPTH) {
+                Some(depth) => Some(usize::from_str(depth).map_err(|_| KeyError::ParseError)?),
+                None => None,
+            };
+            tree(&visible(database, command), prefix, depth)
+        }
+        constants::EDIT => {
+            let prefix = retrieve_prefix(command, database.divider(), current)?;
+            // The file to edit is written out in plain text
+            if !command.is_present(constants::REVEAL) && holds_secrets(database, prefix.as_ref()) {
+                return Err(io::Error::other(
+                    "Secret values below the key, pass --reveal to edit them",
+                )
+                .into());
+            }
+            let changes = edit::edit(database, prefix)?;
+            describe(database, &changes, command.is_present(constants::REVEAL));
+            Ok(())
+        }
+        constants::DIFF => compare(
+            database,
+            db_file,
+            name,
+            command,
+            current,
+            |before, after| diff(before.diff(after), database.divider()),
+        ),
+        constants::PATCH => match command.subcommand() {
+            Some((constants::CREATE, create)) => {
+                compare(database, db_file, name, create, current, |before, after| {
+                    create_patch(before, after, database.divider())
+                })
+            }
+            Some((constants::APPLY, apply)) => apply_patch(
+                database,
+                apply.value_of(constants::PATCH_FILE).unwrap(),
+                retrieve_prefix(apply, database.divider(), current)?,
+                apply.is_present(constants::DRY_RUN),
+            ),
+            _ => {
+                no_command_found();
+                Ok(())
+            }
+        },
+        constants::LS => ls(
+            &visible(database, command),
+            retrieve_prefix(command, database.divider(), current)?,
+            command.is_present(constants::LONG),
+        ),
+        constants::SEARCH => {
+            let pattern =
+                Regex::new(command.value_of(constants::PATTERN).unwrap()).map_err(|error| {
+                    eprintln!("{}", error);
+                    KeyError::ParseError
+                })?;
+            let scope = if command.is_present(constants::KEYS) {
+                SearchScope::Keys
+            } else if command.is_present(constants::VALUES) {
+                SearchScope::Values
+            } else {
+                SearchScope::Both
+            };
+            search(
+                &visible(database, command),
+                &pattern,
+                retrieve_prefix(command, database.divider(), current)?,
+                scope,
+            )
+        }
+        constants::SECRET => secret(
+            database,
+            match command.values_of("key") {
+                Some(_) => Some(retrieve_key(command, database.divider(), current)?),
+                None => None,
+            },
+            command.is_present(constants::UNMARK),
+        ),
+        constants::COLLATE => {
+            let names: Vec<&str> = command.values_of(constants::COLLATION).unwrap().collect();
+            database.set_collation(Collation::from_str(&names.join(","))?)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The database with secret values masked, unless --reveal was passed.
+fn visible<'a>(
+    database: &'a Database<String, String>,
+    command: &ArgMatches,
+) -> Cow<'a, Database<String, String>> {
+    if command.is_present(constants::REVEAL) || database.secrets().is_empty() {
+        Cow::Borrowed(database)
+    } else {
+        Cow::Owned(database.masked(&constants::SECRET_MASK.into()))
+    }
+}
+
+/// True when a value below prefix, or anywhere without one, is secret.
+fn holds_secrets(database: &Database<String, String>, prefix: Option<&Key<String>>) -> bool {
+    let pairs = match prefix {
+        Some(prefix) => database.get_values(prefix),
+        None => database.generate_key_value_pairs(),
+    };
+    pairs.iter().any(|(key, _)| database.is_secret(key))
+}
+
+/// Marks or unmarks key as secret, without a key lists the marked keys.
+fn secret(
+    database: &mut Database<String, String>,
+    key: Option<Key<String>>,
+    unmark: bool,
+) -> Result<(), DBError> {
+    match key {
+        Some(key) if unmark => {
+            if !database.unmark_secret(&key) {
+                return Err(DBError::KeyNotFound);
+            }
+        }
+        Some(key) => database.mark_secret(key),
+        None => {
+            for secret in database.secrets() {
+                println!("{}", secret.to_string(database.divider()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn retrieve_output(matches: &ArgMatches) -> OutputOptions {
+    OutputOptions {
+        format: matches
+            .value_of(constants::OUTPUT)
+            .and_then(|format| OutputFormat::from_str(format).ok())
+            .unwrap_or_default(),
+        raw: matches.is_present(constants::RAW),
+        null: matches.is_present(constants::NULL),
+    }
+}
+
+/// The key of -k, split at the divider of the database.
+fn retrieve_key(
+    matches: &ArgMatches,
+    divider: char,
+    current: Option<&Key<String>>,
+) -> Result<Key<String>, KeyError> {
+    resolve_key(
+        split_key(matches.values_of("key").unwrap(), divider),
+        current,
+    )
+}
+
+/// Like retrieve_key, for subcommands where the key is optional.
+/// Without a key the current key is used.
+fn retrieve_prefix(
+    matches: &ArgMatches,
+    divider: char,
+    current: Option<&Key<String>>,
+) -> Result<Option<Key<String>>, KeyError> {
+    match matches.values_of("key") {
+        Some(_) => retrieve_key(matches, divider, current).map(Some),
+        None => Ok(current.cloned()),
+    }
+}
+
+/// Segments of the values of a key argument, each split at divider.
+fn split_key<'a, I>(values: I, divider: char) -> Vec<String>
+where
+    I: Iterator<Item = &'a str>,
+{
+    values
+        .flat_map(|value| value.split(divider))
+        .map(String::from)
+        .collect()
+}
+
+/// Segments starting with an empty one, like `.org.team`, are absolute.
+/// Anything else is appended to the current key.
+fn resolve_key(
+    mut segments: Vec<String>,
+    current: Option<&Key<String>>,
+) -> Result<Key<String>, KeyError> {
+    if segments.len() > 1 && segments[0].is_empty() {
+        segments.remove(0);
+        return Key::new_from_vec(segments);
+    }
+    let key = Key::new_from_vec(segments)?;
+    match current {
+        Some(current) => {
+            let mut resolved = current.clone();
+            resolved.add(&key);
+            Ok(resolved)
+        }
+        None => Ok(key),
+    }
+}
+
+/// Condition a value has to meet before add writes it.
+#[derive(Clone)]
+pub enum AddCondition {
+    Always,
+    IfAbsent,
+    IfEquals(String),
 }
 
 pub fn add(
     database: &mut Database<String, String>,
     key: Key<String>,
     value: String,
+    condition: AddCondition,
 ) -> Result<(), DBError> {
-    if let Err(e) = database.insert(key, value) {
-        let stdout = io::stdout();
-        let mut handle = stdout.lock();
+    match condition {
+        AddCondition::Always => database.insert(key, value),
+        AddCondition::IfAbsent => database.insert_if_absent(key, value),
+        AddCondition::IfEquals(old) => database.compare_and_swap(&key, Some(&old), value),
+    }
+}
 
-        let output = format!("Insert Error: {0}", e);
-        handle.write_all(output.as_bytes())?;
-        handle.flush()?;
+/// Adds every key<TAB>value line of reader, the key split at the divider of
+/// database. Nothing is added when a line can not be parsed.
+pub fn add_all<R>(
+    database: &mut Database<String, String>,
+    reader: &mut R,
+    current: Option<&Key<String>>,
+    condition: AddCondition,
+) -> Result<(), DBError>
+where
+    R: Read,
+{
+    let mut values = Vec::new();
+    for (index, line) in io::BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = line.split_once('\t').and_then(|(key, value)| {
+            let key = Key::new_from_str(key, database.divider()).ok()?;
+            Some((key, value.to_string()))
+        });
+        match parsed {
+            Some(pair) => values.push(pair),
+            None => return Err(DBError::ParseError { line: index + 1 }),
+        }
+    }
+    for (key, value) in values {
+        let key = match current {
+            Some(current) => {
+                let mut resolved = current.clone();
+                resolved.add(&key);
+                resolved
+            }
+            None => key,
+        };
+        add(database, key, value, condition.clone())?;
+    }
+    Ok(())
+}
 
-        return Err(e);
+/// Resolves the value argument of add, - reads standard input and @file reads
+/// the file. One trailing line ending is dropped, like shell substitution does.
+fn read_value(value: &str) -> Result<String, DBError> {
+    let mut contents = match value {
+        "-" => {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            contents
+        }
+        _ => match value.strip_prefix('@') {
+            Some(file) => std::fs::read_to_string(file)?,
+            None => return Ok(value.to_string()),
+        },
+    };
+    if contents.ends_with('\n') {
+        contents.pop();
+        if contents.ends_with('\r') {
+            contents.pop();
+        }
     }
+    Ok(contents)
+}
 
-    Ok(())
+/// How get and print write key value pairs to standard out.
+/// Without raw or null, the pairs are rendered in format.
+#[derive(Default)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Only write values, one per line.
+    pub raw: bool,
+    /// Terminate every key and value with NUL instead of a newline.
+    pub null: bool,
+}
+
+pub fn flush_to_stdout(
+    database: &Database<String, String>,
+    output: OutputOptions,
+) -> Result<(), DBError> {
+    if !output.raw && !output.null {
+        return database.render(&output.format, &mut io::stdout());
+    }
+    write_plain(
+        database.generate_key_value_pairs(),
+        database.divider(),
+        &output,
+    )
+}
+
+pub fn get(
+    database: &Database<String, String>,
+    key: Key<String>,
+    output: OutputOptions,
+) -> Result<(), DBError> {
+    let key_value_pairs = database.get_values(&key);
+    if key_value_pairs.is_empty() {
+        return Err(DBError::KeyNotFound);
+    }
+    if !output.raw && !output.null {
+        return database.render_values(&key, &output.format, &mut io::stdout());
+    }
+    write_plain(key_value_pairs, database.divider(), &output)
 }
 
-pub fn flush_to_stdout(database: &mut Database<String, String>) -> Result<(), DBError> {
-    database.print_cli()
+pub fn tree(
+    database: &Database<String, String>,
+    prefix: Option<Key<String>>,
+    depth: Option<usize>,
+) -> Result<(), DBError> {
+    let renderer = TreeRenderer {
+        depth,
+        width: terminal_size::terminal_size().map(|(width, _)| width.0 as usize),
+    };
+    renderer.render(database, prefix.as_ref(), &mut io::stdout())
 }
 
-pub fn get(database: &mut Database<String, String>, key: Key<String>) -> Result<(), DBError> {
-    database.get_values_cli(&key)
+/// Works out what diff and patch create compare: two files, the database and
+/// a file, or two keys of the database. With files a single key narrows both sides.
+/// Files are loaded like the database file, only a missing one fails.
+fn compare<F>(
+    database: &Database<String, String>,
+    db_file: &DbFile,
+    name: &str,
+    command: &ArgMatches,
+    current: Option<&Key<String>>,
+    compare_with: F,
+) -> Result<(), DBError>
+where
+    F: FnOnce(&Database<String, String>, &Database<String, String>) -> Result<(), DBError>,
+{
+    let mut files = Vec::new();
+    for file in command.values_of(constants::FILES).into_iter().flatten() {
+        std::fs::metadata(file)?;
+        files.push(db_file.with_path(file).load()?);
+    }
+    let mut keys = Vec::new();
+    for segments in command.grouped_values_of("key").into_iter().flatten() {
+        keys.push(resolve_key(
+            split_key(segments.into_iter(), database.divider()),
+            current,
+        )?);
+    }
+
+    let (before, after) = match (files.as_slice(), keys.as_slice()) {
+        ([before, after], [] | [_]) => (before, after),
+        ([after], [] | [_]) => (database, after),
+        ([], [_, _]) => (database, database),
+        _ => {
+            eprintln!("{} takes two files, one file, or two keys", name);
+            return Err(KeyError::ParseError.into());
+        }
+    };
+    let (before_key, after_key) = match keys.as_slice() {
+        [key] => (Some(key), Some(key)),
+        [before_key, after_key] => (Some(before_key), Some(after_key)),
+        _ => (None, None),
+    };
+    let (before, after) = (visible(before, command), visible(after, command));
+    let empty = Database::new();
+    compare_with(
+        subtree(&before, before_key, &empty)?,
+        subtree(&after, after_key, &empty)?,
+    )
 }
 
-pub fn remove(database: &mut Database<String, String>, key: Key<String>) -> Result<(), DBError> {
+/// Writes the changes between two databases as a patch to standard out.
+pub fn create_patch(
+    before: &Database<String, String>,
+    after: &Database<String, String>,
+    divider: char,
+) -> Result<(), DBError> {
+    Patch::from_changes(before.diff(after)).write(&mut io::stdout(), divider)
+}
+
+/// Applies a patch file, - reads standard input. Keys of the patch are
+/// relative to prefix. A dry run prints what would change instead.
+pub fn apply_patch(
+    database: &mut Database<String, String>,
+    file: &str,
+    prefix: Option<Key<String>>,
+    dry_run: bool,
+) -> Result<(), DBError> {
+    let mut patch = match file {
+        "-" => Patch::read(&mut io::stdin(), database.divider())?,
+        _ => Patch::read(&mut File::open(file)?, database.divider())?,
+    };
+    if let Some(prefix) = &prefix {
+        patch = patch.prefixed(prefix);
+    }
+
+    let failed: Vec<Operation<String, String>> =
+        database.check_patch(&patch).into_iter().cloned().collect();
+    if !failed.is_empty() {
+        eprintln!("The database does not hold the values these operations expect:");
+        Patch { operations: failed }.write(&mut io::stderr(), database.divider())?;
+        return Err(DBError::ConditionFailed);
+    }
+    if !dry_run {
+        return database.apply_patch(&patch);
+    }
+    let mut patched = database.clone();
+    patched.apply_patch(&patch)?;
+    let mask = constants::SECRET_MASK.to_string();
+    let changes = database
+        .diff(&patched)
+        .into_iter()
+        .map(|change| {
+            if database.is_secret(change.key()) {
+                change.masked(&mask)
+            } else {
+                change
+            }
+        })
+        .collect();
+    diff(changes, database.divider())
+}
+
+/// The parent at key, or an empty database when key is missing.
+fn subtree<'a>(
+    database: &'a Database<String, String>,
+    key: Option<&Key<String>>,
+    empty: &'a Database<String, String>,
+) -> Result<&'a Database<String, String>, DBError> {
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(database),
+    };
+    match database.get_node(key) {
+        Some(NodeType::Parent(parent)) => Ok(parent),
+        Some(NodeType::Value(_)) => Err(DBError::MultiKeyExtendValueKey {
+            key: key.clone(),
+            existing: key.clone(),
+            divider: database.divider(),
+            line: None,
+        }),
+        None => Ok(empty),
+    }
+}
+
+/// Prints every difference, keys are relative to the compared keys.
+/// Lines start with + when added, - when removed, ~ when changed from the
+/// first to the second value and ! when a value turned into a parent or back.
+pub fn diff(changes: Vec<Difference<String, String>>, divider: char) -> Result<(), DBError> {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for change in changes {
+        let line = match change {
+            Difference::Added { key, value } => {
+                format!("+ {}\t{}\n", key.to_string(divider), value)
+            }
+            Difference::Removed { key, value } => {
+                format!("- {}\t{}\n", key.to_string(divider), value)
+            }
+            Difference::Changed { key, before, after } => {
+                format!("~ {}\t{}\t{}\n", key.to_string(divider), before, after)
+            }
+            Difference::TypeChanged { key, before, after } => format!(
+                "! {}\t{}\t{}\n",
+                key.to_string(divider),
+                before.as_deref().unwrap_or(constants::DIFF_PARENT),
+                after.as_deref().unwrap_or(constants::DIFF_PARENT)
+            ),
+        };
+        handle.write_all(line.as_bytes())?;
+    }
+    handle.flush()?;
+    Ok(())
+}
+
+/// Lists the direct children of prefix, parents end with the divider.
+/// Long adds the node type, the number of values below it and a value preview.
+pub fn ls(
+    database: &Database<String, String>,
+    prefix: Option<Key<String>>,
+    long: bool,
+) -> Result<(), DBError> {
+    let children = match &prefix {
+        None => database
+            .nodes()
+            .into_iter()
+            .map(|(segment, node)| (segment.clone(), node))
+            .collect(),
+        Some(key) => match database.get_node(key).ok_or(DBError::KeyNotFound)? {
+            NodeType::Parent(_) => database.children(key),
+            node => vec![(key.clone(), node)],
+        },
+    };
+
     let stdout = io::stdout();
     let mut handle = stdout.lock();
-    if let Some(value) = database.remove(&key) {
-        let output = format!("Removed: {0}\n", value);
-        handle.write_all(output.as_bytes())?;
+    let divider = database.divider();
+    for (segment, node) in children {
+        let mut name = segment.to_string(divider);
+        let line = match node {
+            NodeType::Parent(parent) => {
+                name.push(divider);
+                if long {
+                    format!("parent {:>5} {}\n", parent.value_count(), name)
+                } else {
+                    format!("{}\n", name)
+                }
+            }
+            NodeType::Value(value) => {
+                if long {
+                    format!("value  {:>5} {}  {}\n", 1, name, preview(value))
+                } else {
+                    format!("{}\n", name)
+                }
+            }
+        };
+        handle.write_all(line.as_bytes())?;
     }
     handle.flush()?;
     Ok(())
 }
 
-pub fn save_to_disk(database: &mut Database<String, String>, db_file: &str) -> Result<(), DBError> {
-    let mut writer = OpenOptions::new()
-        .read(false)
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(db_file)?;
-    trace!("Write Database file opened.");
+/// Prints the pairs below prefix that match pattern as a table.
+/// Matches are highlighted when standard out is a terminal.
+pub fn search(
+    database: &Database<String, String>,
+    pattern: &Regex,
+    prefix: Option<Key<String>>,
+    scope: SearchScope,
+) -> Result<(), DBError> {
+    let highlight = io::stdout().is_terminal();
+    let prefix = prefix.map(|prefix| database.stored(&prefix));
+    let entries: Vec<Entry> = database
+        .search(pattern, scope)
+        .into_iter()
+        .filter(|(key, _)| prefix.as_ref().is_none_or(|prefix| key.starts_with(prefix)))
+        .map(|(key, value)| {
+            let key_string = key.to_string(database.divider());
+            let (key_string, value) = if highlight {
+                let key_string = match scope {
+                    SearchScope::Values => key_string,
+                    _ => highlight_matches(pattern, &key_string),
+                };
+                let value = match scope {
+                    SearchScope::Keys => value.clone(),
+                    _ => highlight_matches(pattern, value),
+                };
+                (key_string, value)
+            } else {
+                (key_string, value.clone())
+            };
+            // The segments of the key itself, a segment may hold another divider
+            Entry {
+                segments: (0..key.size())
+                    .filter_map(|index| key.get_inner_key(index))
+                    .map(|segment| segment.to_string(database.divider()))
+                    .collect(),
+                key: key_string,
+                value,
+            }
+        })
+        .collect();
+    if entries.is_empty() {
+        return Err(DBError::KeyNotFound);
+    }
+    OutputFormat::Table.render(&entries, &mut io::stdout())
+}
+
+fn highlight_matches(pattern: &Regex, text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    // Empty matches have nothing to highlight
+    for range in pattern
+        .find_ranges(text)
+        .into_iter()
+        .filter(|range| !range.is_empty())
+    {
+        result.push_str(&text[last..range.start]);
+        result.push_str(constants::HIGHLIGHT_START);
+        result.push_str(&text[range.clone()]);
+        result.push_str(constants::HIGHLIGHT_END);
+        last = range.end;
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// First line of the value, shortened to PREVIEW_LENGTH characters.
+fn preview(value: &str) -> String {
+    let first_line = value.lines().next().unwrap_or_default();
+    if first_line.chars().count() > constants::PREVIEW_LENGTH || first_line.len() < value.len() {
+        let mut preview: String = first_line
+            .chars()
+            .take(constants::PREVIEW_LENGTH - 1)
+            .collect();
+        preview.push('…');
+        preview
+    } else {
+        first_line.into()
+    }
+}
+
+/// Keys are written with the divider of the database.
+fn write_plain(
+    key_value_pairs: Vec<(Key<String>, &String)>,
+    divider: char,
+    output: &OutputOptions,
+) -> Result<(), DBError> {
+    let terminator = if output.null { '\0' } else { '\n' };
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    for (key, value) in key_value_pairs {
+        let mut line = String::new();
+        if !output.raw {
+            line.push_str(&key.to_string(divider));
+            line.push(if output.null { terminator } else { '\t' });
+        }
+        line.push_str(value);
+        line.push(terminator);
+        handle.write_all(line.as_bytes())?;
+    }
+    handle.flush()?;
+    Ok(())
+}
 
-    database.flush(&mut writer)
+pub fn remove(database: &mut Database<String, String>, key: Key<String>) -> Result<(), DBError> {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    let secret = database.is_secret(&key);
+    match database.remove(&key) {
+        Some(value) => {
+            let value = if secret {
+                constants::SECRET_MASK.into()
+            } else {
+                value
+            };
+            let output = format!("Removed: {0}\n", value);
+            handle.write_all(output.as_bytes())?;
+            handle.flush()?;
+            Ok(())
+        }
+        None => Err(DBError::KeyNotFound),
+    }
 }
@@ -1,5 +1,10 @@
 use crate::constants;
 use clap::{self, App, AppSettings, Arg};
+use multi_key_db::collation::Collation;
+use multi_key_db::layout::KeyOrder;
+use multi_key_db::load::LoadMode;
+use multi_key_db::merge::MergeStrategy;
+use multi_key_db::render::OutputFormat;
 
 pub fn generate_app() -> App<'static> {
     App::new("Key Value Database")
@@ -9,10 +14,89 @@ pub fn generate_app() -> App<'static> {
         .arg(interactive_arg())
         .arg(db_file_arg())
         .arg(log_arg())
-        .subcommand(add_subcommand())
-        .subcommand(get_subcommand())
-        .subcommand(remove_subcommand())
-        .subcommand(print_subcommand())
+        .arg(keep_going_arg())
+        .arg(encrypted_arg())
+        .arg(load_mode_arg())
+        .arg(drop_skipped_arg())
+        .arg(key_order_arg())
+        .arg(sign_key_arg())
+        .arg(verify_key_arg())
+        .subcommands(database_subcommands())
+        .subcommand(exec_subcommand())
+        .subcommand(merge_driver_subcommand())
+        .subcommand(
+            App::new(constants::ENCRYPT).about("Encrypt the database file with a passphrase"),
+        )
+        .subcommand(
+            App::new(constants::DECRYPT).about("Store the database file without encryption"),
+        )
+        .subcommand(
+            App::new(constants::KEYGEN)
+                .about("Create a key pair to sign database files with")
+                .arg(
+                    Arg::new(constants::NAME)
+                        .about("Writes NAME.key and NAME.pub")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new(constants::VERIFY)
+                .about("Check the checksum of the database file and its signature"),
+        )
+        .subcommand(
+            App::new(constants::FSCK)
+                .about("Report every problem of the database file by line")
+                .arg(
+                    Arg::new(constants::REPAIR)
+                        .long(constants::REPAIR)
+                        .about("Save the entries that load and the rejected lines next to it"),
+                ),
+        )
+}
+
+/// Commands accepted by the interactive mode, the same database
+/// subcommands as the command line plus the session commands.
+pub fn generate_interactive_app() -> App<'static> {
+    App::new("kv db")
+        .setting(AppSettings::NoBinaryName)
+        .setting(AppSettings::DisableVersionFlag)
+        .subcommands(database_subcommands())
+        .subcommand(App::new(constants::SAVE).about("Save the database to disk"))
+        .subcommand(App::new(constants::EXIT).about("Leave interactive mode"))
+        .subcommand(
+            App::new(constants::CD)
+                .about("Change the current key, relative keys start from it")
+                .arg(
+                    Arg::new(constants::PATH).about(
+                        "Key to move to, .. for the parent or a leading divider for absolute",
+                    ),
+                ),
+        )
+        .subcommand(App::new(constants::PWD).about("Print the current key"))
+        .subcommand(App::new(constants::UNDO).about("Revert the changes of the last command"))
+        .subcommand(App::new(constants::REDO).about("Apply the last undone change again"))
+        .subcommand(
+            App::new(constants::CHANGES)
+                .about("List the changes not saved to disk yet")
+                .arg(reveal_arg()),
+        )
+}
+
+fn database_subcommands() -> Vec<App<'static>> {
+    vec![
+        add_subcommand(),
+        get_subcommand(),
+        remove_subcommand(),
+        print_subcommand(),
+        tree_subcommand(),
+        ls_subcommand(),
+        search_subcommand(),
+        edit_subcommand(),
+        diff_subcommand(),
+        patch_subcommand(),
+        secret_subcommand(),
+        collate_subcommand(),
+    ]
 }
 
 fn interactive_arg() -> Arg<'static> {
@@ -32,6 +116,51 @@ fn db_file_arg() -> Arg<'static> {
         .takes_value(true)
 }
 
+fn encrypted_arg() -> Arg<'static> {
+    Arg::new(constants::ENCRYPTED)
+        .short('e')
+        .long(constants::ENCRYPTED)
+        .about("Read and write the database file encrypted with a passphrase")
+        .takes_value(false)
+}
+
+fn load_mode_arg() -> Arg<'static> {
+    Arg::new(constants::LOAD_MODE)
+        .long(constants::LOAD_MODE)
+        .about("Fail on duplicate keys too, or skip the lines that do not load")
+        .possible_values(&LoadMode::NAMES)
+        .takes_value(true)
+}
+
+fn drop_skipped_arg() -> Arg<'static> {
+    Arg::new(constants::DROP_SKIPPED)
+        .long(constants::DROP_SKIPPED)
+        .about("Save even though the lines a lenient load skipped are lost")
+        .takes_value(false)
+}
+
+fn key_order_arg() -> Arg<'static> {
+    Arg::new(constants::KEY_ORDER)
+        .long(constants::KEY_ORDER)
+        .about("Keep keys sorted, or in the order they were added, from now on")
+        .possible_values(&KeyOrder::NAMES)
+        .takes_value(true)
+}
+
+fn sign_key_arg() -> Arg<'static> {
+    Arg::new(constants::SIGN_KEY)
+        .long(constants::SIGN_KEY)
+        .about("Sign the database file on save with the key in this file")
+        .takes_value(true)
+}
+
+fn verify_key_arg() -> Arg<'static> {
+    Arg::new(constants::VERIFY_KEY)
+        .long(constants::VERIFY_KEY)
+        .about("Refuse a database file not signed for this public key file")
+        .takes_value(true)
+}
+
 fn log_arg() -> Arg<'static> {
     Arg::new(constants::LOG)
         .short('l')
@@ -44,8 +173,27 @@ fn add_subcommand() -> App<'static> {
     App::new(constants::ADD)
         .about("Add new key value to database")
         .add_version_author()
-        .arg(key_arg())
-        .arg(value_arg())
+        .arg(
+            key_arg()
+                .required(false)
+                .required_unless_present(constants::STDIN)
+                .requires("value"),
+        )
+        .arg(
+            value_arg()
+                .required(false)
+                .required_unless_present(constants::STDIN)
+                .requires("key"),
+        )
+        .arg(if_absent_arg())
+        .arg(if_equals_arg())
+        .arg(
+            Arg::new(constants::STDIN)
+                .long(constants::STDIN)
+                .about("Add every key<TAB>value line of standard input")
+                .takes_value(false)
+                .conflicts_with_all(&["key", "value", constants::IF_EQUALS]),
+        )
 }
 
 fn get_subcommand() -> App<'static> {
@@ -53,6 +201,10 @@ fn get_subcommand() -> App<'static> {
         .about("Get value(s) from the database")
         .add_version_author()
         .arg(key_arg())
+        .arg(output_arg())
+        .arg(raw_arg())
+        .arg(null_arg())
+        .arg(reveal_arg())
 }
 
 fn remove_subcommand() -> App<'static> {
@@ -65,17 +217,204 @@ fn print_subcommand() -> App<'static> {
     App::new(constants::PRINT)
         .about("Print Database to standard out")
         .add_version_author()
+        .arg(output_arg())
+        .arg(raw_arg())
+        .arg(null_arg())
+        .arg(reveal_arg())
+}
+
+fn tree_subcommand() -> App<'static> {
+    App::new(constants::TREE)
+        .about("Print the database hierarchy as a tree")
+        .add_version_author()
+        .arg(key_arg().required(false))
+        .arg(depth_arg())
+        .arg(reveal_arg())
+}
+
+fn ls_subcommand() -> App<'static> {
+    App::new(constants::LS)
+        .about("List the direct children of a key")
+        .add_version_author()
+        .arg(key_arg().required(false))
+        .arg(long_arg())
+        .arg(reveal_arg())
+}
+
+fn search_subcommand() -> App<'static> {
+    App::new(constants::SEARCH)
+        .about("Search keys and values with a regular expression")
+        .add_version_author()
+        .arg(
+            Arg::new(constants::PATTERN)
+                .about("Regular expression to search for")
+                .required(true),
+        )
+        .arg(key_arg().required(false))
+        .arg(
+            Arg::new(constants::KEYS)
+                .long(constants::KEYS)
+                .about("Only search keys")
+                .takes_value(false)
+                .conflicts_with(constants::VALUES),
+        )
+        .arg(
+            Arg::new(constants::VALUES)
+                .long(constants::VALUES)
+                .about("Only search values")
+                .takes_value(false),
+        )
+        .arg(reveal_arg())
+}
+
+fn secret_subcommand() -> App<'static> {
+    App::new(constants::SECRET)
+        .about("Mark a key and the keys below it secret, or list the secret keys")
+        .add_version_author()
+        .arg(key_arg().required(false))
+        .arg(
+            Arg::new(constants::UNMARK)
+                .long(constants::UNMARK)
+                .about("Remove the secret mark of the key")
+                .takes_value(false)
+                .requires("key"),
+        )
+}
+
+fn collate_subcommand() -> App<'static> {
+    App::new(constants::COLLATE)
+        .about("Change how key segments compare, recorded in the database file")
+        .add_version_author()
+        .arg(collation_arg())
+}
+
+fn collation_arg() -> Arg<'static> {
+    Arg::new(constants::COLLATION)
+        .about("Compare segments by number, without case, or both separated by a comma")
+        .possible_values(&Collation::NAMES)
+        .use_delimiter(true)
+        .takes_value(true)
+        .required(true)
+}
+
+fn reveal_arg() -> Arg<'static> {
+    Arg::new(constants::REVEAL)
+        .long(constants::REVEAL)
+        .about("Show secret values instead of masking them")
+        .takes_value(false)
+}
+
+fn edit_subcommand() -> App<'static> {
+    App::new(constants::EDIT)
+        .about("Edit the values below a key in $EDITOR")
+        .add_version_author()
+        .arg(key_arg().required(false))
+        .arg(reveal_arg().about("Allow editing secret values, they are written out in plain text"))
+}
+
+fn diff_subcommand() -> App<'static> {
+    App::new(constants::DIFF)
+        .about("Show the differences between two database files or two keys")
+        .add_version_author()
+        .arg(compare_files_arg())
+        .arg(key_arg().required(false).multiple_occurrences(true))
+        .arg(reveal_arg())
+}
+
+fn patch_subcommand() -> App<'static> {
+    App::new(constants::PATCH)
+        .about("Create or apply a patch of set, remove and move operations")
+        .add_version_author()
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            App::new(constants::CREATE)
+                .about("Write the changes between two files or two keys as a patch")
+                .arg(compare_files_arg())
+                .arg(key_arg().required(false).multiple_occurrences(true))
+                .arg(reveal_arg()),
+        )
+        .subcommand(
+            App::new(constants::APPLY)
+                .about("Apply every operation of a patch, or none when a value does not match")
+                .arg(
+                    Arg::new(constants::PATCH_FILE)
+                        .about("Patch to apply, - reads standard input")
+                        .required(true),
+                )
+                .arg(key_arg().required(false))
+                .arg(
+                    Arg::new(constants::DRY_RUN)
+                        .long(constants::DRY_RUN)
+                        .about("Print what would change without changing anything")
+                        .takes_value(false),
+                ),
+        )
+}
+
+fn compare_files_arg() -> Arg<'static> {
+    Arg::new(constants::FILES)
+        .about("Files to compare, a single file is compared with the database")
+        .multiple_values(true)
+        .max_values(2)
+}
+
+fn merge_driver_subcommand() -> App<'static> {
+    App::new(constants::MERGE_DRIVER)
+        .about("Three way merge for git, writes the result over the ours file")
+        .add_version_author()
+        .arg(
+            Arg::new(constants::BASE)
+                .about("Common ancestor, %O")
+                .required(true),
+        )
+        .arg(
+            Arg::new(constants::OURS)
+                .about("Current version and merge result, %A")
+                .required(true),
+        )
+        .arg(
+            Arg::new(constants::THEIRS)
+                .about("Other branch version, %B")
+                .required(true),
+        )
+        .arg(
+            Arg::new(constants::STRATEGY)
+                .short('s')
+                .long(constants::STRATEGY)
+                .about("Side that wins a conflict, fail leaves the ours file untouched")
+                .possible_values(&MergeStrategy::NAMES)
+                .default_value("fail"),
+        )
 }
 
+fn exec_subcommand() -> App<'static> {
+    App::new(constants::EXEC)
+        .about("Run the interactive commands of a script file and save once at the end")
+        .add_version_author()
+        .arg(
+            Arg::new(constants::SCRIPT)
+                .about("Script with one command per line, - reads standard input")
+                .required(true),
+        )
+        .arg(keep_going_arg())
+}
+
+fn keep_going_arg() -> Arg<'static> {
+    Arg::new(constants::KEEP_GOING)
+        .long(constants::KEEP_GOING)
+        .about("Keep running a script after a failing line")
+        .takes_value(false)
+}
+
+/// Split at the divider of the database once it is loaded.
 fn key_arg() -> Arg<'static> {
     Arg::new("key")
                         .short('k')
                         .long("key")
-                        .about("Key value to use to find value to remove, Multi Key Structure This.Is.A.Multi.Key is delimited by period")
+                        .about("Key value to use to find value to remove, Multi Key Structure This.Is.A.Multi.Key is delimited by the divider of the database, period by default")
                         .takes_value(true)
                         .min_values(1)
-                        .use_delimiter(true)
-                        .value_delimiter('.')
+                        .use_delimiter(false)
                         .required(true)
 }
 
@@ -83,13 +422,73 @@ fn value_arg() -> Arg<'static> {
     Arg::new("value")
         .short('v')
         .long("value")
-        .about("Value to be added")
+        .about("Value to be added, - reads it from standard input and @file from a file")
         .takes_value(true)
         .max_values(1)
         .use_delimiter(false)
         .required(true)
 }
 
+fn depth_arg() -> Arg<'static> {
+    Arg::new(constants::DEPTH)
+        .short('d')
+        .long(constants::DEPTH)
+        .about("Number of levels below the key to draw")
+        .value_name("N")
+        .takes_value(true)
+        .max_values(1)
+}
+
+fn long_arg() -> Arg<'static> {
+    Arg::new(constants::LONG)
+        .long(constants::LONG)
+        .about("Show type, number of values below and a value preview")
+        .takes_value(false)
+}
+
+fn output_arg() -> Arg<'static> {
+    Arg::new(constants::OUTPUT)
+        .short('o')
+        .long(constants::OUTPUT)
+        .about("Output format")
+        .possible_values(&OutputFormat::NAMES)
+        .default_value("table")
+        .conflicts_with_all(&[constants::RAW, constants::NULL])
+}
+
+fn raw_arg() -> Arg<'static> {
+    Arg::new(constants::RAW)
+        .long(constants::RAW)
+        .about("Print only the value(s), without a table")
+        .takes_value(false)
+}
+
+fn null_arg() -> Arg<'static> {
+    Arg::new(constants::NULL)
+        .short('0')
+        .long(constants::NULL)
+        .about("Separate keys and values with NUL instead of drawing a table, for xargs -0")
+        .takes_value(false)
+}
+
+fn if_absent_arg() -> Arg<'static> {
+    Arg::new(constants::IF_ABSENT)
+        .long(constants::IF_ABSENT)
+        .about("Only add the value if the key does not already hold a value")
+        .takes_value(false)
+        .conflicts_with(constants::IF_EQUALS)
+}
+
+fn if_equals_arg() -> Arg<'static> {
+    Arg::new(constants::IF_EQUALS)
+        .long(constants::IF_EQUALS)
+        .about("Only add the value if the key currently holds this value")
+        .value_name("old")
+        .takes_value(true)
+        .max_values(1)
+        .use_delimiter(false)
+}
+
 trait Extension {
     fn add_version_author(self) -> Self;
 }
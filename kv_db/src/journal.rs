@@ -0,0 +1,239 @@
+use multi_key_db::database::Database;
+use multi_key_db::diff::Change as Difference;
+use multi_key_db::error::DBError;
+use multi_key_db::key::Key;
+
+/// A change to the value of one key, None when the key held no value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub key: Key<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl Change {
+    /// The change that puts the value back.
+    pub fn inverse(&self) -> Change {
+        Change {
+            key: self.key.clone(),
+            before: self.after.clone(),
+            after: self.before.clone(),
+        }
+    }
+
+    /// The change with both values replaced by mask, for output.
+    pub fn masked(&self, mask: &str) -> Change {
+        Change {
+            key: self.key.clone(),
+            before: self.before.as_ref().map(|_| mask.into()),
+            after: self.after.as_ref().map(|_| mask.into()),
+        }
+    }
+
+    /// Diff lines, - for the old value and + for the new one.
+    pub fn describe(&self, divider: char) -> String {
+        let key = self.key.to_string(divider);
+        let mut lines = String::new();
+        if let Some(before) = &self.before {
+            lines.push_str(&format!("- {}\t{}\n", key, before));
+        }
+        if let Some(after) = &self.after {
+            lines.push_str(&format!("+ {}\t{}\n", key, after));
+        }
+        lines
+    }
+
+    pub fn apply(&self, database: &mut Database<String, String>) -> Result<(), DBError> {
+        match &self.after {
+            Some(value) => database.insert(self.key.clone(), value.clone()),
+            None => {
+                database.remove(&self.key);
+                database.prune();
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Applies the changes, removes first so a value can turn into a parent
+/// and back. Returns them in the order they were applied.
+pub fn apply_all(
+    mut changes: Vec<Change>,
+    database: &mut Database<String, String>,
+) -> Result<Vec<Change>, DBError> {
+    changes.sort_by_key(|change| change.after.is_some());
+    for change in &changes {
+        change.apply(database)?;
+    }
+    Ok(changes)
+}
+
+/// Changes made during an interactive session, oldest first, so they can
+/// be undone and redone until the session ends. Every entry holds the
+/// changes of one command.
+#[derive(Debug, Default)]
+pub struct Journal {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+}
+
+impl Journal {
+    /// Records the changes of a command, a new change can not be redone past.
+    /// A command that changed nothing is not recorded.
+    pub fn record(&mut self, changes: Vec<Change>) {
+        if changes.is_empty() {
+            return;
+        }
+        self.undo.push(changes);
+        self.redo.clear();
+    }
+
+    /// Reverts the newest command and returns what was applied.
+    pub fn undo(
+        &mut self,
+        database: &mut Database<String, String>,
+    ) -> Result<Option<Vec<Change>>, DBError> {
+        let changes = match self.undo.last() {
+            Some(changes) => changes.iter().map(Change::inverse).collect(),
+            None => return Ok(None),
+        };
+        let applied = apply_all(changes, database)?;
+        self.redo.extend(self.undo.pop());
+        Ok(Some(applied))
+    }
+
+    /// Applies the newest undone command again and returns what was applied.
+    pub fn redo(
+        &mut self,
+        database: &mut Database<String, String>,
+    ) -> Result<Option<Vec<Change>>, DBError> {
+        let changes = match self.redo.last() {
+            Some(changes) => changes.clone(),
+            None => return Ok(None),
+        };
+        let applied = apply_all(changes, database)?;
+        self.undo.extend(self.redo.pop());
+        Ok(Some(applied))
+    }
+}
+
+/// Every key whose value differs between saved and current, sorted by key.
+pub fn changes(
+    saved: &Database<String, String>,
+    current: &Database<String, String>,
+) -> Vec<Change> {
+    saved.diff(current).into_iter().map(Change::from).collect()
+}
+
+impl From<Difference<String, String>> for Change {
+    fn from(difference: Difference<String, String>) -> Change {
+        match difference {
+            Difference::Added { key, value } => Change {
+                key,
+                before: None,
+                after: Some(value),
+            },
+            Difference::Removed { key, value } => Change {
+                key,
+                before: Some(value),
+                after: None,
+            },
+            Difference::Changed { key, before, after } => Change {
+                key,
+                before: Some(before),
+                after: Some(after),
+            },
+            Difference::TypeChanged { key, before, after } => Change { key, before, after },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::journal::*;
+
+    fn key(key: &str) -> Key<String> {
+        Key::new_from_str(key, '.').unwrap()
+    }
+
+    fn record_insert(
+        journal: &mut Journal,
+        database: &mut Database<String, String>,
+        name: &str,
+        value: &str,
+    ) {
+        let before = database.get(&key(name)).cloned();
+        database.insert(key(name), value.into()).unwrap();
+        journal.record(vec![Change {
+            key: key(name),
+            before,
+            after: Some(value.into()),
+        }]);
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut database = Database::new();
+        let mut journal = Journal::default();
+        record_insert(&mut journal, &mut database, "org.team", "a");
+        record_insert(&mut journal, &mut database, "org.team", "b");
+
+        let undone = journal.undo(&mut database).unwrap().unwrap();
+        assert_eq!(Some("a".to_string()), undone[0].after);
+        assert_eq!(Some(&"a".to_string()), database.get(&key("org.team")));
+
+        journal.undo(&mut database).unwrap();
+        assert!(database.is_empty());
+        assert_eq!(None, journal.undo(&mut database).unwrap());
+
+        journal.redo(&mut database).unwrap();
+        assert_eq!(Some(&"a".to_string()), database.get(&key("org.team")));
+
+        // A new change drops what could be redone
+        record_insert(&mut journal, &mut database, "org.other", "c");
+        assert_eq!(None, journal.redo(&mut database).unwrap());
+    }
+
+    #[test]
+    fn undo_several_changes() {
+        let mut database = Database::new();
+        database.insert(key("list"), "value".into()).unwrap();
+        let mut journal = Journal::default();
+
+        // One command turns a value into a parent
+        let before = database.clone();
+        database.remove(&key("list"));
+        database.insert(key("list.first"), "1".into()).unwrap();
+        database.insert(key("list.second"), "2".into()).unwrap();
+        journal.record(changes(&before, &database));
+        journal.record(Vec::new());
+
+        assert_eq!(3, journal.undo(&mut database).unwrap().unwrap().len());
+        assert_eq!(Some(&"value".to_string()), database.get(&key("list")));
+        assert_eq!(None, journal.undo(&mut database).unwrap());
+
+        journal.redo(&mut database).unwrap();
+        assert_eq!(Some(&"2".to_string()), database.get(&key("list.second")));
+    }
+
+    #[test]
+    fn changes_against_saved() {
+        let mut saved = Database::new();
+        saved.insert(key("same"), "1".into()).unwrap();
+        saved.insert(key("modified"), "old".into()).unwrap();
+        saved.insert(key("removed"), "gone".into()).unwrap();
+        let mut current = Database::new();
+        current.insert(key("same"), "1".into()).unwrap();
+        current.insert(key("modified"), "new".into()).unwrap();
+        current.insert(key("added"), "here".into()).unwrap();
+
+        let described: String = changes(&saved, &current)
+            .iter()
+            .map(|change| change.describe('.'))
+            .collect();
+        assert_eq!(
+            "+ added\there\n- modified\told\n+ modified\tnew\n- removed\tgone\n",
+            described
+        );
+    }
+}
@@ -1,15 +1,23 @@
+mod completion;
 mod constants;
+mod edit;
 mod event_loop;
 mod flags;
+mod journal;
+mod storage;
+mod tokenizer;
 
-use crate::event_loop::save_to_disk;
 use clap::ArgMatches;
-use event_loop::{add, event_loop, flush_to_stdout, get, remove};
-use log::{debug, error, trace, warn, LevelFilter};
-use multi_key_db::{database::Database, error::DBError, error::KeyError, key::Key};
-use std::fs::OpenOptions;
-use std::io::ErrorKind;
+use event_loop::{event_loop, execute, mutates, run_script, ScriptEnd};
+use log::{debug, error, LevelFilter};
+use multi_key_db::layout::KeyOrder;
+use multi_key_db::load::LoadMode;
+use multi_key_db::merge::{Conflict, MergeStrategy};
+use multi_key_db::{database::Database, error::DBError};
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
 use std::str::FromStr;
+use storage::DbFile;
 
 fn main() {
     let matches = flags::generate_app().get_matches();
@@ -27,101 +35,243 @@ fn main() {
     }
     debug!("Database File Location: {}", db_file);
 
-    let db = create_db(&db_file);
-    if let Err(error) = db {
-        error!("Database creation error: {}", error);
-        return;
+    // decrypt reads the encrypted file and saves it without encryption
+    let encrypted = matches.is_present(constants::ENCRYPTED)
+        || matches.subcommand_name() == Some(constants::DECRYPT);
+    let mut db_file = DbFile::new(&db_file, encrypted);
+    if let Some(mode) = matches.value_of(constants::LOAD_MODE) {
+        match LoadMode::from_str(mode) {
+            Ok(mode) => db_file.set_load_mode(mode),
+            Err(error) => fail("Load mode error", error.into()),
+        }
+    }
+    db_file.set_drop_skipped(matches.is_present(constants::DROP_SKIPPED));
+    if let Err(error) = db_file.set_keys(
+        matches.value_of(constants::SIGN_KEY),
+        matches.value_of(constants::VERIFY_KEY),
+    ) {
+        fail("Key reading error", error);
     }
-    let mut db = db.unwrap();
 
-    if matches.is_present(constants::INTERACTIVE) {
-        if let Err(error) = event_loop(&mut db, &db_file) {
-            error!("Interactive error {}", error);
+    // Work on the files only, the database is not loaded or saved
+    match matches.subcommand() {
+        // Works on the files git passes in, with the settings of the database
+        Some((constants::MERGE_DRIVER, command)) => {
+            if let Err(error) = merge_driver(command, &db_file) {
+                fail("Merge error", error);
+            }
             return;
-        };
-    } else {
-        match matches.subcommand() {
-            Some((constants::ADD, add_command)) => {
-                let multi_key = retrieve_key(add_command);
-                match multi_key {
-                    Err(e) => {
-                        error!("Key creation error: {}", e);
-                        return;
-                    }
-                    Ok(key) => {
-                        let value = add_command.value_of("value").unwrap();
-
-                        if let Err(e) = add(&mut db, key, value.to_string()) {
-                            error!("Database Add Error: {}", e);
-                            return;
-                        }
-                    }
-                }
+        }
+        Some((constants::KEYGEN, command)) => {
+            if let Err(error) = storage::keygen(command.value_of(constants::NAME).unwrap()) {
+                fail("Key generation error", error);
             }
-            Some((constants::GET, get_command)) => {
-                let multi_key = retrieve_key(get_command);
-                match multi_key {
-                    Err(e) => {
-                        error!("Key creation error: {}", e);
-                        return;
-                    }
-                    Ok(key) => {
-                        if let Err(e) = get(&mut db, key) {
-                            error!("Database Get Error: {}", e);
-                            return;
-                        }
-                    }
-                }
+            return;
+        }
+        Some((constants::VERIFY, _)) => {
+            if let Err(error) = db_file.verify() {
+                fail("Verification failure", error);
             }
-            Some((constants::REMOVE, remove_command)) => match retrieve_key(remove_command) {
-                Err(e) => {
-                    error!("Key creation error: {}", e);
-                    return;
-                }
-                Ok(key) => {
-                    if let Err(e) = remove(&mut db, key) {
-                        error!("Database Remove Error: {}", e);
-                        return;
-                    }
-                }
+            return;
+        }
+        Some((constants::FSCK, command)) => {
+            if let Err(error) = fsck(&db_file, command.is_present(constants::REPAIR)) {
+                fail("Check failure", error);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    // Commands that may change the database hold the lock from the load to
+    // the save, so a conditional add sees the value of the last writer.
+    // An interactive session on a terminal would hold it for as long as it runs.
+    let writes = match matches.subcommand() {
+        _ if matches.is_present(constants::INTERACTIVE) => !io::stdin().is_terminal(),
+        Some((constants::EXEC | constants::ENCRYPT | constants::DECRYPT, _)) => true,
+        Some((name, command)) => mutates(name, command),
+        None => false,
+    };
+    let _lock = match writes.then(|| db_file.lock()).transpose() {
+        Ok(lock) => lock,
+        Err(error) => fail("Database locking error", error),
+    };
+
+    let mut db = match db_file.load() {
+        Ok(db) => db,
+        Err(error) => fail("Database creation error", error),
+    };
+    if let Some(order) = matches.value_of(constants::KEY_ORDER) {
+        match KeyOrder::from_str(order) {
+            Ok(order) => db.set_key_order(order),
+            Err(error) => fail("Key order error", error.into()),
+        }
+    }
+
+    // Only changes are written, a file read as it is stays as it is
+    let changed = if matches.is_present(constants::INTERACTIVE) && !io::stdin().is_terminal() {
+        let keep_going = matches.is_present(constants::KEEP_GOING);
+        exec(&mut db, &db_file, io::stdin().lock(), keep_going)
+    } else if matches.is_present(constants::INTERACTIVE) {
+        match event_loop(&mut db, &db_file) {
+            Ok(changed) => changed,
+            Err(error) => fail("Interactive error", error),
+        }
+    } else if let Some((constants::EXEC, command)) = matches.subcommand() {
+        let keep_going = command.is_present(constants::KEEP_GOING);
+        match command.value_of(constants::SCRIPT) {
+            Some("-") | None => exec(&mut db, &db_file, io::stdin().lock(), keep_going),
+            Some(script) => match File::open(script) {
+                Ok(file) => exec(&mut db, &db_file, BufReader::new(file), keep_going),
+                Err(error) => fail("Script open error", error.into()),
             },
-            Some((constants::PRINT, _)) => {
-                if let Err(error) = flush_to_stdout(&mut db) {
-                    error!("Database writing to standard out failure: {}", error);
+        }
+    } else if let Some((constants::ENCRYPT, _)) = matches.subcommand() {
+        db_file.set_encrypted(true);
+        true
+    } else if let Some((constants::DECRYPT, _)) = matches.subcommand() {
+        db_file.set_encrypted(false);
+        true
+    } else if let Some((name, command)) = matches.subcommand() {
+        if let Err(error) = execute(&mut db, &db_file, name, command, None) {
+            fail(&format!("Database {} error", name), error);
+        }
+        mutates(name, command)
+    } else {
+        false
+    };
+
+    if changed {
+        if let Err(error) = db_file.save(&mut db) {
+            fail("Database writing to disk failure", error);
+        }
+    }
+}
+
+/// Runs a script, true when it changed the database. A failing script is
+/// saved once, when keep_going let it finish, and exits with the code of
+/// its first failure.
+fn exec<R>(db: &mut Database<String, String>, db_file: &DbFile, script: R, keep_going: bool) -> bool
+where
+    R: io::BufRead,
+{
+    match run_script(db, db_file, script, keep_going) {
+        Ok(ScriptEnd {
+            error: None,
+            changed,
+        }) => changed,
+        Ok(ScriptEnd {
+            error: Some(error),
+            changed,
+        }) => {
+            if changed {
+                if let Err(error) = db_file.save(db) {
+                    fail("Database writing to disk failure", error);
                 }
             }
-            _ => (),
+            script_failed(error);
         }
+        Err(error) => script_failed(error),
     }
+}
+
+/// The failing line has already been reported with its line number.
+fn script_failed(error: DBError) -> ! {
+    error!("Script error: {}", error);
+    std::process::exit(exit_code(&error));
+}
+
+/// Merges the base, ours and theirs files and writes the result over ours.
+/// Every conflict is reported, with the fail strategy ours is left untouched.
+fn merge_driver(command: &ArgMatches, db_file: &DbFile) -> Result<(), DBError> {
+    // Ours first, so the passphrase it may ask for carries over to the others
+    let ours_file = db_file.with_path(command.value_of(constants::OURS).unwrap());
+    let ours = ours_file.load()?;
+    let load = |name: &str| ours_file.with_path(command.value_of(name).unwrap()).load();
+    let strategy = MergeStrategy::from_str(command.value_of(constants::STRATEGY).unwrap())?;
+    let mut merged = Database::merge(
+        &load(constants::BASE)?,
+        &ours,
+        &load(constants::THEIRS)?,
+        strategy,
+    )?;
 
-    if let Err(error) = save_to_disk(&mut db, &db_file) {
-        error!("Database writing to disk failure: {}", error);
-        return;
+    let describe = |value: &Option<String>| value.clone().unwrap_or_else(|| "(none)".into());
+    for conflict in &merged.conflicts {
+        let key = conflict.key().to_string(ours.divider());
+        match conflict {
+            Conflict::Value { ours, theirs, .. } => eprintln!(
+                "Conflict {}: ours {}, theirs {}",
+                key,
+                describe(ours),
+                describe(theirs)
+            ),
+            Conflict::Structure { .. } => eprintln!(
+                "Conflict {}: a value on one side, keys below it on the other",
+                key
+            ),
+        }
+    }
+    if !merged.is_clean() && strategy == MergeStrategy::Fail {
+        return Err(DBError::MergeConflict);
     }
+    ours_file.save(&mut merged.database)
 }
-fn retrieve_key(matches: &ArgMatches) -> Result<Key<String>, KeyError> {
-    Key::new_from_vec(
-        matches
-            .values_of("key")
-            .unwrap()
-            .map(|s| s.into())
-            .collect(),
-    )
+
+/// Prints every problem of the database file, failing unless repair saved them aside.
+fn fsck(db_file: &DbFile, repair: bool) -> Result<(), DBError> {
+    let mut fsck = db_file.fsck()?;
+    for problem in &fsck.problems {
+        println!("{}", problem);
+        for line in problem.text.lines() {
+            println!("    {}", line);
+        }
+    }
+    if fsck.is_clean() {
+        println!("No problems found");
+        return Ok(());
+    }
+    println!("Problems found: {}", fsck.problems.len());
+    if !repair {
+        return Err(DBError::CorruptDBFile {
+            line: Some(fsck.problems[0].line),
+        });
+    }
+    let repaired = format!("{}{}", db_file.path, constants::REPAIRED_EXTENSION);
+    db_file.with_path(&repaired).save(&mut fsck.database)?;
+    let rejected = format!("{}{}", db_file.path, constants::REJECTED_EXTENSION);
+    // The rejected lines of an encrypted file are in plain text
+    fsck.write_rejected(&mut storage::create_private(&rejected)?)?;
+    println!("Repaired: {}", repaired);
+    println!("Rejected: {}", rejected);
+    Ok(())
 }
 
-fn create_db(db_file: &str) -> Result<Database<String, String>, DBError> {
-    let reader = OpenOptions::new().read(true).open(db_file);
-    trace!("Read Database file opened.");
-
-    match reader {
-        Ok(mut reader) => Database::new_from_file(&mut reader),
-        Err(error) => {
-            if error.kind() == ErrorKind::NotFound {
-                warn!("File not found, empty database created.");
-                Ok(Database::new())
-            } else {
-                Err(DBError::IOError(error))
-            }
+/// Logs the error, reports it on standard error and exits with the matching code.
+fn fail(context: &str, error: DBError) -> ! {
+    error!("{}: {}", context, error);
+    eprint!("{}", error);
+    std::process::exit(exit_code(&error));
+}
+
+fn exit_code(error: &DBError) -> i32 {
+    match error {
+        DBError::IOError(_) => constants::EXIT_IO_ERROR,
+        DBError::KeyError(_)
+        | DBError::ParseError { .. }
+        | DBError::CorruptDBFile { .. }
+        | DBError::SkippedLines { .. } => constants::EXIT_PARSE_ERROR,
+        DBError::KeyNotFound => constants::EXIT_KEY_NOT_FOUND,
+        DBError::InsertValueToDirectory { .. }
+        | DBError::MultiKeyExtendValueKey { .. }
+        | DBError::CollationConflict { .. } => constants::EXIT_CONFLICT,
+        DBError::ConditionFailed => constants::EXIT_CONDITION_FAILED,
+        DBError::MergeConflict => constants::EXIT_MERGE_CONFLICT,
+        DBError::PassphraseRequired | DBError::DecryptionFailed | DBError::SealingRequired => {
+            constants::EXIT_ENCRYPTION
+        }
+        DBError::ChecksumMismatch | DBError::SignatureInvalid | DBError::SigningKeyRequired => {
+            constants::EXIT_VERIFICATION
         }
     }
 }
@@ -0,0 +1,314 @@
+use crate::constants;
+use log::{debug, trace, warn};
+use multi_key_db::checksum;
+use multi_key_db::database::Database;
+use multi_key_db::encryption;
+use multi_key_db::error::DBError;
+use multi_key_db::fsck::Fsck;
+use multi_key_db::load::{LoadMode, LoadOptions};
+use multi_key_db::signing;
+use std::cell::{OnceCell, RefCell};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use tempfile::Builder;
+
+/// The database file on disk. The whole file is encrypted when encrypted is
+/// set, secret values are sealed one by one otherwise. The passphrase is
+/// only asked for once one of them needs it.
+/// With a signing key every save writes a signature next to the file, with a
+/// public key the file is only loaded when that signature matches.
+/// Lines a lenient load skipped are only dropped by a save with drop_skipped.
+#[derive(Debug, Clone)]
+pub struct DbFile {
+    pub path: String,
+    encrypted: bool,
+    load_options: LoadOptions,
+    skipped: RefCell<Vec<usize>>,
+    drop_skipped: bool,
+    passphrase: OnceCell<String>,
+    signing_key: Option<String>,
+    public_key: Option<String>,
+}
+
+impl DbFile {
+    pub fn new(path: &str, encrypted: bool) -> DbFile {
+        DbFile {
+            path: path.into(),
+            encrypted,
+            load_options: LoadOptions::default(),
+            skipped: RefCell::new(Vec::new()),
+            drop_skipped: false,
+            passphrase: OnceCell::new(),
+            signing_key: None,
+            public_key: None,
+        }
+    }
+
+    /// Files holding the signing key and the public key, as written by keygen.
+    pub fn set_keys(
+        &mut self,
+        signing_key: Option<&str>,
+        public_key: Option<&str>,
+    ) -> Result<(), DBError> {
+        self.signing_key = signing_key.map(fs::read_to_string).transpose()?;
+        self.public_key = public_key.map(fs::read_to_string).transpose()?;
+        Ok(())
+    }
+
+    pub fn signature_path(&self) -> String {
+        format!("{}{}", self.path, constants::SIGNATURE_EXTENSION)
+    }
+
+    /// Waits for the exclusive lock of the file and holds it until the
+    /// returned file is dropped. The lock is taken on a file next to the
+    /// database, which itself is replaced on every save.
+    pub fn lock(&self) -> Result<File, DBError> {
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(format!("{}{}", self.path, constants::LOCK_EXTENSION))?;
+        lock.lock()?;
+        trace!("Database file locked.");
+        Ok(lock)
+    }
+
+    pub fn set_load_mode(&mut self, mode: LoadMode) {
+        self.load_options.mode = mode;
+    }
+
+    pub fn set_drop_skipped(&mut self, drop_skipped: bool) {
+        self.drop_skipped = drop_skipped;
+    }
+
+    /// Changes how the next save writes the file.
+    pub fn set_encrypted(&mut self, encrypted: bool) {
+        self.encrypted = encrypted;
+    }
+
+    /// The same file settings and keys for another path.
+    pub fn with_path(&self, path: &str) -> DbFile {
+        DbFile {
+            path: path.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Reads the database, a missing file is an empty database.
+    /// Problems the load mode lets pass are reported on standard error.
+    pub fn load(&self) -> Result<Database<String, String>, DBError> {
+        let reader = OpenOptions::new().read(true).open(&self.path);
+        trace!("Read Database file opened.");
+
+        let mut contents = Vec::new();
+        match reader {
+            Ok(mut reader) => reader.read_to_end(&mut contents)?,
+            // A missing file was not signed either
+            Err(error) if error.kind() == ErrorKind::NotFound && self.public_key.is_none() => {
+                warn!("File not found, empty database created.");
+                return Ok(Database::new());
+            }
+            Err(error) => return Err(DBError::IOError(error)),
+        };
+        self.verify_signature(&contents)?;
+        let (database, report) = self.parse(
+            &contents,
+            |file| Database::load(file, &self.load_options),
+            |file, passphrase| Database::load_encrypted(file, passphrase, &self.load_options),
+        )?;
+        for warning in &report.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        self.skipped.replace(report.skipped_lines());
+        Ok(database)
+    }
+
+    /// Checks every entry of the file, see Database::fsck.
+    pub fn fsck(&self) -> Result<Fsck<String, String>, DBError> {
+        let contents = fs::read(&self.path)?;
+        self.verify_signature(&contents)?;
+        self.parse(&contents, Database::fsck, Database::fsck_encrypted)
+    }
+
+    /// Reads contents with plain, or with encrypted once a passphrase is needed.
+    fn parse<'c, T, P, E>(&self, contents: &'c [u8], plain: P, encrypted: E) -> Result<T, DBError>
+    where
+        P: Fn(&mut &'c [u8]) -> Result<T, DBError>,
+        E: Fn(&mut &'c [u8], &str) -> Result<T, DBError>,
+    {
+        if self.encrypted {
+            return encrypted(&mut &contents[..], self.passphrase(false)?);
+        }
+        // Only an explicit --encrypted reads and writes a whole encrypted file
+        if encryption::is_encrypted(contents) {
+            return Err(DBError::PassphraseRequired);
+        }
+        match plain(&mut &contents[..]) {
+            // Sealed secret values
+            Err(DBError::PassphraseRequired) => {
+                encrypted(&mut &contents[..], self.passphrase(false)?)
+            }
+            result => result,
+        }
+    }
+
+    /// Writes the database and its signature, each atomically.
+    /// A file verified with a public key is only written with a signing key,
+    /// the signature would not match it otherwise.
+    pub fn save(&self, database: &mut Database<String, String>) -> Result<(), DBError> {
+        if self.public_key.is_some() && self.signing_key.is_none() {
+            return Err(DBError::SigningKeyRequired);
+        }
+        let skipped = self.skipped.borrow();
+        if !skipped.is_empty() && !self.drop_skipped {
+            return Err(DBError::SkippedLines {
+                lines: skipped.clone(),
+            });
+        }
+        let mut contents = Vec::new();
+        if self.encrypted {
+            database.flush_encrypted(&mut contents, self.passphrase(true)?)?;
+        } else if !database.secrets().is_empty() {
+            database.flush_sealed(&mut contents, self.passphrase(true)?)?;
+        } else {
+            database.flush(&mut contents)?;
+        }
+        if let Some(signing_key) = &self.signing_key {
+            let signature = signing::sign(&contents, signing_key)?;
+            write_atomically(
+                &self.signature_path(),
+                format!("{}\n", signature).as_bytes(),
+            )?;
+        }
+        write_atomically(&self.path, &contents)
+    }
+
+    /// Checks the signature and the checksum of the file without loading it.
+    /// Prints what was checked, a file without a checksum fails.
+    pub fn verify(&self) -> Result<(), DBError> {
+        let mut contents = fs::read(&self.path)?;
+        if self.public_key.is_some() {
+            self.verify_signature(&contents)?;
+            println!("Signature: valid");
+        }
+        if encryption::is_encrypted(&contents) {
+            contents = encryption::decrypt(&contents, self.passphrase(false)?)?;
+        }
+        let contents =
+            String::from_utf8(contents).map_err(|_| DBError::CorruptDBFile { line: None })?;
+        if !checksum::is_present(&contents) {
+            println!("Checksum: missing");
+            return Err(DBError::ChecksumMismatch);
+        }
+        if let Err(error) = checksum::verify(&contents) {
+            println!("Checksum: does not match");
+            return Err(error);
+        }
+        println!("Checksum: valid");
+        Ok(())
+    }
+
+    fn verify_signature(&self, contents: &[u8]) -> Result<(), DBError> {
+        let public_key = match &self.public_key {
+            Some(public_key) => public_key,
+            None => return Ok(()),
+        };
+        let signature = match fs::read_to_string(self.signature_path()) {
+            Ok(signature) => signature,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err(DBError::SignatureInvalid)
+            }
+            Err(error) => return Err(error.into()),
+        };
+        signing::verify(contents, &signature, public_key)
+    }
+
+    fn passphrase(&self, new: bool) -> Result<&str, DBError> {
+        if let Some(passphrase) = self.passphrase.get() {
+            return Ok(passphrase);
+        }
+        let passphrase = read_passphrase(new)?;
+        Ok(self.passphrase.get_or_init(|| passphrase))
+    }
+}
+
+/// Writes contents to a new file next to path first and then renames it
+/// over path, so a failed write never leaves a half written file.
+/// The file keeps the permissions it had, a new one is only readable by the
+/// owner where the platform supports it.
+fn write_atomically(path: &str, contents: &[u8]) -> Result<(), DBError> {
+    let directory = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // A name nobody can guess, created only if it does not exist yet
+    let mut writer = Builder::new()
+        .prefix(".kv_db-")
+        .suffix(".tmp")
+        .tempfile_in(directory)?;
+    trace!("Write file opened: {}", writer.path().display());
+    if let Ok(metadata) = fs::metadata(path) {
+        writer.as_file().set_permissions(metadata.permissions())?;
+    }
+    writer.write_all(contents)?;
+    writer.as_file().sync_all()?;
+    writer.persist(path).map_err(|error| error.error)?;
+    Ok(())
+}
+
+/// Creates or truncates a file only the owner can read, where the platform
+/// supports it. An existing file loses the permissions it had.
+pub fn create_private(path: &str) -> Result<File, DBError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    Ok(file)
+}
+
+/// Writes a new key pair to name.key and name.pub, the key is only
+/// readable by the owner where the platform supports it.
+pub fn keygen(name: &str) -> Result<(), DBError> {
+    let (signing_key, public_key) = signing::generate();
+    let key_file = format!("{}.key", name);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    writeln!(options.open(&key_file)?, "{}", signing_key)?;
+    fs::write(format!("{}.pub", name), format!("{}\n", public_key))?;
+    println!("Signing key: {}", key_file);
+    println!("Public key: {}.pub", name);
+    Ok(())
+}
+
+/// The passphrase from KV_DB_PASSPHRASE, or asked for on the terminal.
+/// A new passphrase is asked for twice, to catch typing mistakes.
+/// An empty passphrase is refused either way.
+fn read_passphrase(new: bool) -> Result<String, DBError> {
+    if let Ok(passphrase) = std::env::var(constants::PASSPHRASE_VARIABLE) {
+        if passphrase.is_empty() {
+            return Err(DBError::PassphraseRequired);
+        }
+        return Ok(passphrase);
+    }
+    let prompt = |prompt: &str| {
+        rpassword::prompt_password(prompt).map_err(|error| {
+            // No terminal to ask on
+            debug!("Passphrase prompt failed: {}", error);
+            DBError::PassphraseRequired
+        })
+    };
+    let passphrase = prompt("Passphrase: ")?;
+    if new && prompt("Repeat passphrase: ")? != passphrase {
+        return Err(std::io::Error::other("Passphrases do not match").into());
+    }
+    if passphrase.is_empty() {
+        return Err(DBError::PassphraseRequired);
+    }
+    Ok(passphrase)
+}
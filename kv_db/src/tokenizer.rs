@@ -0,0 +1,170 @@
+use crate::constants;
+use std::fmt;
+
+/// Errors from splitting an interactive line into arguments.
+#[derive(Debug, PartialEq)]
+pub enum TokenizeError {
+    UnterminatedQuote(char),
+    TrailingEscape,
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenizeError::UnterminatedQuote(quote) => {
+                writeln!(f, "Missing closing quote {}", quote)
+            }
+            TokenizeError::TrailingEscape => writeln!(f, "Line ends with an escape character"),
+        }
+    }
+}
+
+impl std::error::Error for TokenizeError {}
+
+/// Splits a line into arguments the way a shell would.
+/// Whitespace separates arguments, single quotes keep everything literally,
+/// double quotes allow escaping `"` and `\`, and outside of quotes a
+/// backslash escapes any character.
+pub fn tokenize(line: &str) -> Result<Vec<String>, TokenizeError> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    // Separate from token being empty, so "" is kept as an empty argument
+    let mut in_token = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_token = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => token.push(c),
+                        None => return Err(TokenizeError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                in_token = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') => token.push(c),
+                            Some(c) => {
+                                token.push('\\');
+                                token.push(c);
+                            }
+                            None => return Err(TokenizeError::UnterminatedQuote('"')),
+                        },
+                        Some(c) => token.push(c),
+                        None => return Err(TokenizeError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            '\\' => {
+                in_token = true;
+                match chars.next() {
+                    Some(c) => token.push(c),
+                    None => return Err(TokenizeError::TrailingEscape),
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            c => {
+                in_token = true;
+                token.push(c);
+            }
+        }
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Expands the `key=value` shorthand of add into `-k key -v value`.
+pub fn expand_shorthand(tokens: Vec<String>) -> Vec<String> {
+    if tokens.first().map(String::as_str) != Some(constants::ADD) {
+        return tokens;
+    }
+    let mut expanded = Vec::with_capacity(tokens.len() + 2);
+    let mut previous_takes_value = false;
+    for token in tokens {
+        if !previous_takes_value && !token.starts_with('-') {
+            if let Some((key, value)) = token.split_once('=') {
+                expanded.push("-k".into());
+                expanded.push(key.into());
+                expanded.push("-v".into());
+                expanded.push(value.into());
+                continue;
+            }
+        }
+        previous_takes_value = matches!(
+            token.as_str(),
+            "-k" | "--key" | "-v" | "--value" | "--if-equals"
+        );
+        expanded.push(token);
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tokenizer::*;
+
+    fn strings(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    #[test]
+    fn tokenize_whitespace() {
+        assert_eq!(
+            Ok(strings(&["add", "-k", "a.b", "-v", "c"])),
+            tokenize("  add  -k a.b\t-v c\r\n")
+        );
+        assert_eq!(Ok(Vec::new()), tokenize("   \n"));
+    }
+
+    #[test]
+    fn tokenize_quotes_and_escapes() {
+        assert_eq!(
+            Ok(strings(&["-v", "two words", "it's", "a\"b\\c", "", "x y"])),
+            tokenize(r#"-v "two words" it\'s "a\"b\\c" '' x\ y"#)
+        );
+        assert_eq!(Ok(strings(&[r"a\nb"])), tokenize(r#"'a\nb'"#));
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(
+            Err(TokenizeError::UnterminatedQuote('"')),
+            tokenize("add \"open")
+        );
+        assert_eq!(
+            Err(TokenizeError::UnterminatedQuote('\'')),
+            tokenize("add 'open")
+        );
+        assert_eq!(Err(TokenizeError::TrailingEscape), tokenize("add \\"));
+    }
+
+    #[test]
+    fn shorthand() {
+        assert_eq!(
+            strings(&["add", "-k", "a.b", "-v", "c=d", "--if-absent"]),
+            expand_shorthand(strings(&["add", "a.b=c=d", "--if-absent"]))
+        );
+        assert_eq!(
+            strings(&["add", "-v", "x=y", "-k", "a"]),
+            expand_shorthand(strings(&["add", "-v", "x=y", "-k", "a"]))
+        );
+        assert_eq!(
+            strings(&["get", "-k", "a=b"]),
+            expand_shorthand(strings(&["get", "-k", "a=b"]))
+        );
+    }
+}
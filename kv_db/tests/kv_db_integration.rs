@@ -1,9 +1,11 @@
 use assert_cmd::prelude::*; // Add methods on commands
+use multi_key_db::checksum;
 use multi_key_db::database::Database;
 use multi_key_db::key::Key;
 use predicates::prelude::*; // Used for writing assertions
 use std::error::Error;
 use std::fs::OpenOptions;
+use std::path::Path;
 use std::process::Command;
 use tempfile::tempdir; // Run programs
 
@@ -65,6 +67,1553 @@ fn print() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+fn add_if_absent() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "deploy.lock", "-v", "job-1", "--if-absent"]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "deploy.lock", "-v", "job-2", "--if-absent"]);
+    cmd.assert().failure().code(6);
+
+    // A key below the value is a conflict
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "add",
+        "-k",
+        "deploy.lock.owner",
+        "-v",
+        "job-2",
+        "--if-absent",
+    ]);
+    cmd.assert().failure().code(5);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "add",
+        "-k",
+        "deploy.lock",
+        "-v",
+        "free",
+        "--if-equals",
+        "job-1",
+    ]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "add",
+        "-k",
+        "deploy.lock",
+        "-v",
+        "job-2",
+        "--if-equals",
+        "job-1",
+    ]);
+    cmd.assert().failure().code(6);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "deploy.lock"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("free"));
+
+    Ok(())
+}
+
+#[test]
+fn add_if_absent_concurrently() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    // Only one of the jobs racing for the lock gets it
+    let jobs = (0..8)
+        .map(|job| {
+            db_command(&db_file)?
+                .args(["add", "-k", "deploy.lock", "-v", &format!("job-{}", job)])
+                .arg("--if-absent")
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .map_err(Box::<dyn Error>::from)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut codes = Vec::new();
+    for mut job in jobs {
+        codes.push(job.wait()?.code());
+    }
+    codes.sort();
+    let mut expected = vec![Some(6); 7];
+    expected.insert(0, Some(0));
+    assert_eq!(expected, codes);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "deploy.lock", "--raw"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("job-"));
+    Ok(())
+}
+
+#[test]
+fn get_raw_and_exit_codes() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "add",
+        "-k",
+        "work.team.git",
+        "-v",
+        "github.com/example-repo",
+    ]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "work.team.git", "--raw"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("github.com/example-repo\n"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "work", "--null"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("work.team.git\0github.com/example-repo\0"));
+
+    // Keys are printed with the divider of the file
+    let split_file = dir.path().join("split.db");
+    std::fs::write(&split_file, "split:/\nwork/team.git\trepo\n")?;
+    let mut cmd = db_command(&split_file)?;
+    cmd.args(["print", "--null"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("work/team.git\0repo\0"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "missing"]);
+    cmd.assert().failure().code(4);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "work.team", "-v", "directory"]);
+    cmd.assert().failure().code(5);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "work.team.git.url", "-v", "value"]);
+    cmd.assert()
+        .failure()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "work.team.git.url below the value of work.team.git",
+        ));
+
+    std::fs::write(&db_file, "work\tvalue\nCorruptDB")?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("print");
+    cmd.assert()
+        .failure()
+        .code(3)
+        .stderr("Database is corrupted on line 2.\n");
+
+    // A mistyped flag is a usage error, told apart from a corrupt file
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["print", "--no-such-flag"]);
+    cmd.assert().failure().code(2);
+
+    Ok(())
+}
+
+#[test]
+fn print_output_formats() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "add",
+        "-k",
+        "work.team.git",
+        "-v",
+        "github.com/example-repo",
+    ]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["print", "--output", "json"]);
+    cmd.assert().success().stdout(predicate::eq(
+        "{\n  \"work\": {\n    \"team\": {\n      \"git\": \"github.com/example-repo\"\n    }\n  }\n}\n",
+    ));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "work", "-o", "csv"]);
+    cmd.assert().success().stdout(predicate::eq(
+        "key,value\nwork.team.git,github.com/example-repo\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn tree() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    for (key, value) in [
+        ("work.team.git", "github.com/example-repo"),
+        ("work.name", "kv"),
+    ] {
+        let mut cmd = db_command(&db_file)?;
+        cmd.args(["add", "-k", key, "-v", value]);
+        cmd.assert().success();
+    }
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["tree", "-k", "work", "--depth", "1"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("work (2)\n├── name: kv\n└── team (1)\n"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("tree");
+    cmd.assert().success().stdout(predicate::eq(
+        ".\n└── work (2)\n    ├── name: kv\n    └── team (1)\n        └── git: github.com/example-repo\n",
+    ));
+
+    // The tree output format draws the same tree
+    let tree = String::from_utf8(db_command(&db_file)?.arg("tree").output()?.stdout)?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["print", "-o", "tree"]);
+    cmd.assert().success().stdout(predicate::eq(tree.as_str()));
+
+    Ok(())
+}
+
+#[test]
+fn ls() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    for (key, value) in [
+        ("services.web.port", "80"),
+        ("services.web.host", "example.com"),
+        ("services.db", "postgres"),
+    ] {
+        let mut cmd = db_command(&db_file)?;
+        cmd.args(["add", "-k", key, "-v", value]);
+        cmd.assert().success();
+    }
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["ls", "-k", "services"]);
+    cmd.assert().success().stdout(predicate::eq("db\nweb.\n"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["ls", "-k", "services", "--long"]);
+    cmd.assert().success().stdout(predicate::eq(
+        "value      1 db  postgres\nparent     2 web.\n",
+    ));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("ls");
+    cmd.assert().success().stdout(predicate::eq("services.\n"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["ls", "-k", "missing"]);
+    cmd.assert().failure().code(4);
+
+    // Parents end with the divider of the file
+    let split_file = dir.path().join("split.db");
+    std::fs::write(&split_file, "split:/\napp/services.web/port\t80\n")?;
+    let mut cmd = db_command(&split_file)?;
+    cmd.args(["ls", "-k", "app"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("services.web/\n"));
+
+    Ok(())
+}
+
+#[test]
+fn search() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    for (key, value) in [
+        ("web.host", "old-host.example.com"),
+        ("db.host", "new-host.example.com"),
+        ("old.flag", "true"),
+    ] {
+        let mut cmd = db_command(&db_file)?;
+        cmd.args(["add", "-k", key, "-v", value]);
+        cmd.assert().success();
+    }
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["search", "old-.*", "--values"]);
+    cmd.assert().success().stdout(
+        predicate::str::contains("web.host")
+            .and(predicate::str::contains("db.host").not())
+            .and(predicate::str::contains("old.flag").not()),
+    );
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["search", "host", "-k", "db"]);
+    cmd.assert().success().stdout(
+        predicate::str::contains("db.host").and(predicate::str::contains("web.host").not()),
+    );
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["search", "("]);
+    cmd.assert().failure().code(3);
+
+    let split_file = dir.path().join("split.db");
+    std::fs::write(&split_file, "split:/\napp/web.host\tlocalhost\n")?;
+    let mut cmd = db_command(&split_file)?;
+    cmd.args(["search", "local"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("app/web.host"));
+
+    Ok(())
+}
+
+#[test]
+fn interactive_tokenizer() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(&db_file).arg("-i").write_stdin(
+        "add  -v \"two  words\" -k 'work.team lead'\n\
+         add work.git=github.com/example-repo\n\
+         get -k work --output tsv\n\
+         exit\n",
+    );
+    cmd.assert().success().stdout(
+        predicate::str::contains("work.team lead\ttwo  words\n").and(predicate::str::contains(
+            "work.git\tgithub.com/example-repo\n",
+        )),
+    );
+
+    Ok(())
+}
+
+#[test]
+fn interactive_current_key() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(&db_file).arg("-i").write_stdin(
+        "add org.team.service.prod=up\n\
+         cd org.team\n\
+         pwd\n\
+         add service.dev=down\n\
+         ls\n\
+         get -k .org.team.service.prod --raw\n\
+         cd ..\n\
+         pwd\n\
+         cd\n\
+         pwd\n\
+         exit\n",
+    );
+    cmd.assert().success().stdout(predicate::str::ends_with(
+        "org.team\nservice.\nup\norg\n.\n",
+    ));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "org.team.service.dev", "--raw"]);
+    cmd.assert().success().stdout(predicate::eq("down\n"));
+
+    // Paths use the divider of the file
+    let split_file = dir.path().join("split.db");
+    std::fs::write(&split_file, "split:/\norg/team.a/service\tup\n")?;
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&split_file)
+        .arg("-i")
+        .write_stdin("cd org/team.a\npwd\ncd /org\npwd\nexit\n");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::ends_with("org/team.a\norg\n"));
+
+    Ok(())
+}
+
+#[test]
+fn interactive_undo_redo() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "org.name", "-v", "old"]);
+    cmd.assert().success();
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(&db_file).arg("-i").write_stdin(
+        "remove -k org.name\n\
+         undo\n\
+         add org.name=new\n\
+         add org.team=core\n\
+         undo\n\
+         redo\n\
+         changes\n\
+         exit\n",
+    );
+    cmd.assert().success().stdout(predicate::str::ends_with(
+        "+ org.name\told\n\
+         - org.team\tcore\n\
+         + org.team\tcore\n\
+         - org.name\told\n\
+         + org.name\tnew\n\
+         + org.team\tcore\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn interactive_undo_divider() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(&db_file, "split:/\nwork/team/git\tgithub.com\n")?;
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(&db_file).arg("-i").write_stdin(
+        "remove -k work/team/git\n\
+         undo\n\
+         redo\n\
+         changes\n\
+         exit\n",
+    );
+    cmd.assert().success().stdout(predicate::str::ends_with(
+        "+ work/team/git\tgithub.com\n\
+         - work/team/git\tgithub.com\n\
+         - work/team/git\tgithub.com\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn keys_split_at_divider() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(&db_file, "split:/\nhosts/example.com/ip\t10.0.0.1\n")?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "hosts/example.com/ip", "--raw"]);
+    cmd.assert().success().stdout("10.0.0.1\n");
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "hosts/example.org/ip", "-v", "10.0.0.2"]);
+    cmd.assert().success();
+    assert_eq!(
+        "split:/\nhosts/example.com/ip\t10.0.0.1\nhosts/example.org/ip\t10.0.0.2\n",
+        values(&db_file)?
+    );
+
+    // What pwd prints is a key again
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .arg("-i")
+        .write_stdin("cd hosts/example.com\npwd\nget -k /hosts/example.com/ip --raw\n");
+    cmd.assert()
+        .success()
+        .stdout("hosts/example.com\n10.0.0.1\n");
+
+    Ok(())
+}
+
+#[test]
+fn interactive_undo_patch() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let patch_file = dir.path().join("change.patch");
+    std::fs::write(&patch_file, "set\thost\n- a\n+ b\nremove\tdebug\n- true\n")?;
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(&db_file).arg("-i").write_stdin(format!(
+        "add host=a\n\
+         add debug=true\n\
+         patch apply {}\n\
+         undo\n\
+         changes\n\
+         exit\n",
+        patch_file.display()
+    ));
+    // The whole patch is undone, the adds before it stay
+    cmd.assert().success().stdout(predicate::str::ends_with(
+        "+ debug\ttrue\n\
+         - host\tb\n\
+         + host\ta\n\
+         + debug\ttrue\n\
+         + host\ta\n",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn interactive_add_stdin_rejected() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(&db_file).arg("-i").write_stdin(
+        "add --stdin\n\
+         a\t1\n\
+         add -k b -v -\n\
+         print -o tsv\n\
+         exit\n",
+    );
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "Standard input belongs to the session",
+    ));
+    assert!(!db_file.exists());
+
+    Ok(())
+}
+
+#[test]
+fn add_from_stdin_and_file() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let cert_file = dir.path().join("cert.pem");
+    std::fs::write(&cert_file, "-----BEGIN-----\nabc\n-----END-----\n")?;
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["add", "-k", "motd", "-v", "-"])
+        .write_stdin("hello\nworld\n");
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "tls.cert", "-v"])
+        .arg(format!("@{}", cert_file.display()));
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "tls.cert", "--raw"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("-----BEGIN-----\nabc\n-----END-----\n"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "motd", "--raw"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("hello\nworld\n"));
+
+    Ok(())
+}
+
+#[test]
+fn add_stdin_bulk() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["add", "--stdin"])
+        .write_stdin("a.one\t1\na.two\t2\nb\t3\n");
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["print", "-o", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("a.one\t1\na.two\t2\nb\t3\n"));
+
+    // One bad line and nothing is added
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["add", "--stdin"])
+        .write_stdin("c\t4\nno tab\n");
+    cmd.assert().code(3);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "c"]);
+    cmd.assert().code(4);
+
+    // Keys split at the divider of the file and header-like lines are data
+    let split_file = dir.path().join("split.db");
+    std::fs::write(&split_file, "split:/\na/b\t1\n")?;
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&split_file)
+        .args(["add", "--stdin"])
+        .write_stdin("c.d\t3\ne/f\t4\nsplit:.\t5\n");
+    cmd.assert().success();
+
+    let mut cmd = db_command(&split_file)?;
+    cmd.args(["print", "-o", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("a/b\t1\nc.d\t3\ne/f\t4\nsplit:.\t5\n"));
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&split_file)
+        .args(["add", "--stdin"])
+        .write_stdin("g\t6\nno tab\n");
+    cmd.assert()
+        .code(3)
+        .stderr(predicate::str::contains("line 2"));
+
+    Ok(())
+}
+
+#[test]
+fn add_without_value() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "a"]);
+    cmd.assert().code(2).stderr(
+        predicate::str::contains("--value").and(predicate::str::contains("panicked").not()),
+    );
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .arg("-i")
+        .write_stdin("add -k q\nexit\n");
+    cmd.assert().code(3).stderr(
+        predicate::str::contains("line 1: error").and(predicate::str::contains("panicked").not()),
+    );
+    assert!(!db_file.exists());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_subtree() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    // Breaks the file on the first run and fixes it on the second
+    let editor = dir.path().join("editor.sh");
+    std::fs::write(
+        &editor,
+        "#!/bin/sh\n\
+         if [ ! -f \"$0.ran\" ]; then touch \"$0.ran\"; echo 'no tab' >> \"$1\"; exit 0; fi\n\
+         sed -i -e 's/old/new/' -e '/no tab/d' \"$1\"\n\
+         printf 'added\\tyes\\n' >> \"$1\"\n",
+    )?;
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755))?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "app.prod.host", "-v", "old"]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("EDITOR", &editor).args(["edit", "-k", "app.prod"]);
+    cmd.assert()
+        .success()
+        .stderr(predicate::str::contains("Opening the editor again"))
+        .stdout(predicate::eq(
+            "+ app.prod.added\tyes\n- app.prod.host\told\n+ app.prod.host\tnew\n",
+        ));
+
+    // Leaving the editor with an error changes nothing
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("EDITOR", "false").args(["edit", "-k", "app"]);
+    cmd.assert().code(1);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["print", "-o", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("app.prod.added\tyes\napp.prod.host\tnew\n"));
+
+    Ok(())
+}
+
+#[test]
+fn edit_keeps_divider_and_collation() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let contents =
+        "split:/\ncollation:natural\nhosts/example.com\t1\nhosts/web10\t3\nhosts/web9\t2\n";
+    std::fs::write(&db_file, contents)?;
+
+    // An edit changing nothing keeps every key as it is
+    for args in [&["edit"][..], &["edit", "-k", "hosts"]] {
+        let mut cmd = db_command(&db_file)?;
+        cmd.env("EDITOR", "true").args(args);
+        cmd.assert().success().stdout("");
+        assert_eq!(contents, values(&db_file)?);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_conflict_reopens() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    // Adds a key below a value, then gives up
+    let editor = dir.path().join("editor.sh");
+    std::fs::write(
+        &editor,
+        "#!/bin/sh\n\
+         if [ -f \"$0.ran\" ]; then exit 1; fi\n\
+         touch \"$0.ran\"; stat -c %a \"$1\" > \"$0.mode\"\n\
+         printf 'x\\t1\\n' >> \"$1\"\n",
+    )?;
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755))?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "app", "-v", "value"]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("EDITOR", &editor).args(["edit", "-k", "app.sub"]);
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("below the value of app"))
+        .stderr(predicate::str::contains("Opening the editor again"));
+    assert_eq!(
+        "600\n",
+        std::fs::read_to_string(dir.path().join("editor.sh.mode"))?
+    );
+    assert_eq!("app\tvalue\n", values(&db_file)?);
+
+    Ok(())
+}
+
+#[test]
+fn diff() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let staging = dir.path().join("staging.db");
+    let prod = dir.path().join("prod.db");
+    std::fs::write(&staging, "env.host\ts1\nenv.debug\ttrue\nenv.tls\toff\n")?;
+    std::fs::write(&prod, "env.host\tp1\nenv.replicas\t3\nenv.tls.cert\tc\n")?;
+    let expected = "- debug\ttrue\n~ host\ts1\tp1\n+ replicas\t3\n\
+                    ! tls\toff\t(parent)\n+ tls.cert\tc\n";
+
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.arg("diff").arg(&staging).arg(&prod).args(["-k", "env"]);
+    cmd.assert().success().stdout(predicate::eq(expected));
+
+    let db_file = dir.path().join("kv.db");
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["add", "--stdin"])
+        .write_stdin("staging.host\ts1\nstaging.debug\ttrue\nstaging.tls\toff\nprod.host\tp1\nprod.replicas\t3\nprod.tls.cert\tc\n");
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["diff", "-k", "staging", "-k", "prod"]);
+    cmd.assert().success().stdout(predicate::eq(expected));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["diff", "-k", "staging"]);
+    cmd.assert().code(3);
+
+    Ok(())
+}
+
+#[test]
+fn patch() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let patch_file = dir.path().join("promote.patch");
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["add", "--stdin"])
+        .write_stdin(
+            "staging.host\ta\nstaging.name\tEx\nstaging.debug\ttrue\n\
+         prod.host\tb\nprod.title\tEx\n",
+        );
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["patch", "create", "-k", "staging", "-k", "prod"]);
+    let output = cmd.output()?;
+    assert!(output.status.success());
+    let expected = "remove\tdebug\n- true\nset\thost\n- a\n+ b\nmove\tname\ttitle\n= Ex\n";
+    assert_eq!(expected, String::from_utf8(output.stdout)?);
+    std::fs::write(&patch_file, expected)?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["patch", "apply"])
+        .arg(&patch_file)
+        .args(["-k", "staging", "--dry-run"]);
+    cmd.assert().success().stdout(predicate::eq(
+        "- staging.debug\ttrue\n~ staging.host\ta\tb\n- staging.name\tEx\n+ staging.title\tEx\n",
+    ));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["patch", "apply"])
+        .arg(&patch_file)
+        .args(["-k", "staging"]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["diff", "-k", "staging", "-k", "prod"]);
+    cmd.assert().success().stdout(predicate::eq(""));
+
+    // The values no longer match, nothing is applied
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["patch", "apply"])
+        .arg(&patch_file)
+        .args(["-k", "staging"]);
+    cmd.assert()
+        .code(6)
+        .stderr(predicate::str::contains("set\tstaging.host\n- a\n+ b\n"));
+
+    Ok(())
+}
+
+#[test]
+fn merge_driver() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let base = dir.path().join("base.db");
+    let ours = dir.path().join("ours.db");
+    let theirs = dir.path().join("theirs.db");
+    std::fs::write(&base, "host\ta\nport\t80\n")?;
+    std::fs::write(&ours, "host\tb\nport\t80\n")?;
+    std::fs::write(&theirs, "host\ta\nport\t443\nreplicas\t3\n")?;
+
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.arg("merge-driver").arg(&base).arg(&ours).arg(&theirs);
+    cmd.assert().success();
+    assert_eq!("host\tb\nport\t443\nreplicas\t3\n", values(&ours)?);
+
+    // Both sides changed host
+    std::fs::write(&theirs, "host\tc\nport\t443\nreplicas\t3\n")?;
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.arg("merge-driver").arg(&base).arg(&ours).arg(&theirs);
+    cmd.assert()
+        .code(7)
+        .stderr(predicate::str::contains("Conflict host: ours b, theirs c"));
+    assert_eq!("host\tb\nport\t443\nreplicas\t3\n", values(&ours)?);
+
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.arg("merge-driver")
+        .arg(&base)
+        .arg(&ours)
+        .arg(&theirs)
+        .args(["--strategy", "theirs"]);
+    cmd.assert().success();
+    assert_eq!("host\tc\nport\t443\nreplicas\t3\n", values(&ours)?);
+
+    Ok(())
+}
+
+#[test]
+fn encrypt_decrypt() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(&db_file, "db.password\thunter2\n")?;
+
+    // An empty passphrase is refused, also from the environment
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "").arg("encrypt");
+    cmd.assert().code(8);
+    assert_eq!("db.password\thunter2\n", std::fs::read_to_string(&db_file)?);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret").arg("encrypt");
+    cmd.assert().success();
+    let encrypted = std::fs::read(&db_file)?;
+    assert!(encrypted.starts_with(b"KVDBENC1"));
+    assert!(!encrypted.windows(7).any(|window| window == b"hunter2"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "db.password"]);
+    cmd.assert().code(8);
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "wrong")
+        .args(["--encrypted", "get", "-k", "db.password"]);
+    cmd.assert().code(8);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret").args([
+        "--encrypted",
+        "add",
+        "-k",
+        "db.user",
+        "-v",
+        "admin",
+    ]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["-e", "get", "-k", "db.user", "--raw"]);
+    cmd.assert().success().stdout("admin\n");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret").arg("decrypt");
+    cmd.assert().success();
+    assert_eq!("db.password\thunter2\ndb.user\tadmin\n", values(&db_file)?);
+
+    Ok(())
+}
+
+#[test]
+fn secret_values() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(&db_file, "db.auth.password\thunter2\ndb.host\tlocalhost\n")?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["secret", "-k", "db.auth"]);
+    cmd.assert().success();
+    let contents = std::fs::read_to_string(&db_file)?;
+    assert!(contents.starts_with("format:checksum\nsecret:db.auth\nsealed:"));
+    assert!(contents.contains("db.host\tlocalhost\n"));
+    assert!(!contents.contains("hunter2"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["print", "--raw"]);
+    cmd.assert().success().stdout("********\nlocalhost\n");
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret").args([
+        "get",
+        "-k",
+        "db.auth.password",
+        "--raw",
+        "--reveal",
+    ]);
+    cmd.assert().success().stdout("hunter2\n");
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret").arg("tree");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("password: ********"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "wrong").arg("print");
+    cmd.assert().code(8);
+
+    // Diffs and patches mask secret values too, edit needs --reveal
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["diff", "-k", "db.auth", "-k", "db"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("********"))
+        .stdout(predicate::str::contains("hunter2").not());
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["patch", "create", "-k", "db.auth", "-k", "db", "--reveal"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("hunter2"));
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .env("EDITOR", "true")
+        .args(["edit", "-k", "db"]);
+    cmd.assert()
+        .code(1)
+        .stderr(predicate::str::contains("pass --reveal to edit them"));
+
+    // The merge driver opens sealed values with the passphrase
+    let (base, theirs) = (dir.path().join("base.db"), dir.path().join("theirs.db"));
+    std::fs::copy(&db_file, &base)?;
+    std::fs::copy(&db_file, &theirs)?;
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .arg("merge-driver")
+        .arg(&base)
+        .arg(&db_file)
+        .arg(&theirs);
+    cmd.assert().success();
+    let contents = std::fs::read_to_string(&db_file)?;
+    assert!(contents.starts_with("format:checksum\nsecret:db.auth\nsealed:"));
+    assert!(!contents.contains("hunter2"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["secret", "-k", "db.auth", "--unmark"]);
+    cmd.assert().success();
+    assert_eq!(
+        "db.auth.password\thunter2\ndb.host\tlocalhost\n",
+        values(&db_file)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn secret_values_masked_in_changes() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let patch_file = dir.path().join("rotate.patch");
+    std::fs::write(&db_file, "db.auth.password\thunter2\ndb.host\tlocalhost\n")?;
+    std::fs::write(
+        &patch_file,
+        "set\tdb.auth.password\n- hunter2\n+ swordfish\n",
+    )?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["secret", "-k", "db.auth"]);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .args(["patch", "apply"])
+        .arg(&patch_file)
+        .arg("--dry-run");
+    cmd.assert()
+        .success()
+        .stdout("~ db.auth.password\t********\t********\n");
+
+    // Compared files open sealed values like the database file
+    let copy = dir.path().join("copy.db");
+    std::fs::copy(&db_file, &copy)?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret").arg("diff").arg(&copy);
+    cmd.assert().success().stdout("");
+    let mut cmd = db_command(&db_file)?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .arg("diff")
+        .arg(dir.path().join("missing.db"));
+    cmd.assert().code(1);
+
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.env("KV_DB_PASSPHRASE", "secret")
+        .arg("-f")
+        .arg(&db_file)
+        .arg("-i")
+        .write_stdin(
+            "remove -k db.auth.password\n\
+             undo\n\
+             redo\n\
+             changes\n\
+             changes --reveal\n\
+             exit\n",
+        );
+    cmd.assert().success().stdout(
+        "Removed: ********\n\
+         + db.auth.password\t********\n\
+         - db.auth.password\t********\n\
+         - db.auth.password\t********\n\
+         - db.auth.password\thunter2\n",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn checksum_and_signature() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let keys = dir.path().join("release");
+    let signing_key = dir.path().join("release.key");
+    let public_key = dir.path().join("release.pub");
+
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.arg("keygen").arg(&keys);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--sign-key")
+        .arg(&signing_key)
+        .args(["add", "-k", "app.version", "-v", "1.2.0"]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--verify-key").arg(&public_key).arg("verify");
+    cmd.assert()
+        .success()
+        .stdout("Signature: valid\nChecksum: valid\n");
+
+    // Saved without the key, the signature no longer matches
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "app.debug", "-v", "true"]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--verify-key")
+        .arg(&public_key)
+        .args(["get", "-k", "app.version"]);
+    cmd.assert().code(9);
+
+    // Reading a verified file leaves it and its signature as they are,
+    // changing it needs the signing key
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--sign-key")
+        .arg(&signing_key)
+        .args(["add", "-k", "app.debug", "-v", "false"]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--verify-key")
+        .arg(&public_key)
+        .args(["get", "-k", "app.version"]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--verify-key").arg(&public_key).arg("verify");
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("--verify-key")
+        .arg(&public_key)
+        .args(["add", "-k", "app.debug", "-v", "true"]);
+    cmd.assert().code(9);
+
+    // Dropping the checksum line is noticed
+    let contents = std::fs::read_to_string(&db_file)?;
+    let line = contents.lines().count();
+    let truncated = &contents[..contents.rfind("checksum:").unwrap()];
+    std::fs::write(&db_file, truncated)?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "app.version"]);
+    cmd.assert().code(9);
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("verify");
+    cmd.assert().code(9).stdout("Checksum: missing\n");
+    std::fs::write(&db_file, &contents)?;
+
+    // Changes by hand only load leniently, with a warning
+    std::fs::write(
+        &db_file,
+        format!(
+            "{}# note\napp.name\tweb\n",
+            contents.replace("1.2.0", "6.6.6")
+        ),
+    )?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "app.version"]);
+    cmd.assert().code(9);
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["--load-mode", "lenient", "get", "-k", "app.name", "--raw"]);
+    cmd.assert().success().stdout("web\n").stderr(format!(
+        "Warning: line {}: checksum does not match the file\n",
+        line
+    ));
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("verify");
+    cmd.assert().code(9).stdout("Checksum: does not match\n");
+
+    // Only a save that accepts the changes writes the checksum again
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "--load-mode",
+        "lenient",
+        "add",
+        "-k",
+        "app.debug",
+        "-v",
+        "false",
+    ]);
+    cmd.assert().code(3);
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["--load-mode", "lenient", "--drop-skipped"])
+        .args(["add", "-k", "app.debug", "-v", "false"]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("verify");
+    cmd.assert().success().stdout("Checksum: valid\n");
+
+    Ok(())
+}
+
+#[test]
+fn truncated_file_is_not_checksummed_again() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    for (key, value) in [
+        ("app.name", "web"),
+        ("app.port", "80"),
+        ("app.version", "1.2.0"),
+    ] {
+        let mut cmd = db_command(&db_file)?;
+        cmd.args(["add", "-k", key, "-v", value]);
+        cmd.assert().success();
+    }
+    let contents = std::fs::read_to_string(&db_file)?;
+    std::fs::write(&db_file, &contents[..contents.find("app.port").unwrap()])?;
+
+    // Neither a normal nor a lenient load saves over the truncated file
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "app.debug", "-v", "true"]);
+    cmd.assert().code(9);
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "--load-mode",
+        "lenient",
+        "add",
+        "-k",
+        "app.debug",
+        "-v",
+        "true",
+    ]);
+    cmd.assert().code(3);
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("verify");
+    cmd.assert().code(9).stdout("Checksum: missing\n");
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn save_keeps_permissions() -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let mode = |path: &std::path::Path| -> std::io::Result<u32> {
+        Ok(std::fs::metadata(path)?.permissions().mode() & 0o777)
+    };
+
+    // A new file is only readable by the owner
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "db.password", "-v", "hunter2"]);
+    cmd.assert().success();
+    assert_eq!(0o600, mode(&db_file)?);
+
+    std::fs::set_permissions(&db_file, std::fs::Permissions::from_mode(0o640))?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "db.host", "-v", "localhost"]);
+    cmd.assert().success();
+    assert_eq!(0o640, mode(&db_file)?);
+    // No temporary file is left next to it, only the lock file
+    let mut names: Vec<_> = std::fs::read_dir(dir.path())?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+    assert_eq!(vec!["kv.db", "kv.db.lock"], names);
+    Ok(())
+}
+
+#[test]
+fn fsck() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(
+        &db_file,
+        "db.host\tlocalhost\n\
+         no tab\n\
+         db.port\t80\n\
+         db\tvalue\n\
+         db.port\t8080\n",
+    )?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("fsck");
+    cmd.assert().code(3).stdout(
+        "line 2: no tab between key and value\n    no tab\n\
+         line 3: duplicate key, replaced on line 5\n    db.port\t80\n\
+         line 4: key already holds keys below it, like db.host\n    db\tvalue\n\
+         Problems found: 3\n",
+    );
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["fsck", "--repair"]);
+    cmd.assert().success();
+    let repaired = dir.path().join("kv.db.repaired");
+    assert_eq!("db.host\tlocalhost\ndb.port\t8080\n", values(&repaired)?);
+    assert_eq!(
+        "# line 2: no tab between key and value\nno tab\n\
+         # line 3: duplicate key, replaced on line 5\ndb.port\t80\n\
+         # line 4: key already holds keys below it, like db.host\ndb\tvalue\n",
+        std::fs::read_to_string(dir.path().join("kv.db.rejected"))?
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(dir.path().join("kv.db.rejected"))?;
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+    }
+
+    let mut cmd = db_command(&repaired)?;
+    cmd.arg("fsck");
+    cmd.assert().success().stdout("No problems found\n");
+    Ok(())
+}
+
+#[test]
+fn load_modes() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(&db_file, "app.port\t80\nno tab\napp.port\t8080\n")?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "app.port"]);
+    cmd.assert().code(3);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["--load-mode", "lenient", "get", "-k", "app.port", "--raw"]);
+    cmd.assert().success().stdout("8080\n").stderr(
+        "Warning: line 1: duplicate key, replaced on line 3\n\
+         Warning: line 2: no tab between key and value\n",
+    );
+
+    // Reading leaves the file as it is
+    assert_eq!(
+        "app.port\t80\nno tab\napp.port\t8080\n",
+        std::fs::read_to_string(&db_file)?
+    );
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["--load-mode", "strict", "get", "-k", "app.port"]);
+    cmd.assert().code(3);
+
+    // A change would drop the skipped line, only saved when asked to
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "--load-mode",
+        "lenient",
+        "add",
+        "-k",
+        "app.host",
+        "-v",
+        "web",
+    ]);
+    cmd.assert().code(3).stderr(
+        "Warning: line 1: duplicate key, replaced on line 3\n\
+         Warning: line 2: no tab between key and value\n\
+         Saving would drop the lines the load skipped: 2\n",
+    );
+    assert_eq!(
+        "app.port\t80\nno tab\napp.port\t8080\n",
+        std::fs::read_to_string(&db_file)?
+    );
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "--load-mode",
+        "lenient",
+        "--drop-skipped",
+        "add",
+        "-k",
+        "app.host",
+        "-v",
+        "web",
+    ]);
+    cmd.assert().success();
+    assert_eq!("app.host\tweb\napp.port\t8080\n", values(&db_file)?);
+    Ok(())
+}
+
+#[test]
+fn comments_and_order_kept() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(
+        &db_file,
+        "# Middleware of the api\n\
+         \n\
+         # Runs first\n\
+         api.zlib\ton\n\
+         api.auth\ttoken\n",
+    )?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "api.cache", "-v", "off"]);
+    cmd.assert().success();
+    assert_eq!(
+        "# Middleware of the api\n\
+         \n\
+         # Runs first\n\
+         api.zlib\ton\n\
+         api.auth\ttoken\n\
+         api.cache\toff\n",
+        values(&db_file)?
+    );
+    Ok(())
+}
+
+#[test]
+fn key_order() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "--key-order",
+        "insertion",
+        "add",
+        "-k",
+        "chain.zlib",
+        "-v",
+        "on",
+    ]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "chain.auth", "-v", "token"]);
+    cmd.assert().success();
+    assert_eq!(
+        "order:insertion\n\
+         chain.zlib\ton\n\
+         chain.auth\ttoken\n",
+        values(&db_file)?
+    );
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["print", "-o", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("chain.zlib\ton\nchain.auth\ttoken\n"));
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["--key-order", "sorted", "print", "-o", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("chain.auth\ttoken\nchain.zlib\ton\n"));
+    // Only a command that changes the database writes the order
+    assert!(values(&db_file)?.starts_with("order:insertion\n"));
+    let mut cmd = db_command(&db_file)?;
+    cmd.args([
+        "--key-order",
+        "sorted",
+        "add",
+        "-k",
+        "chain.cache",
+        "-v",
+        "off",
+    ]);
+    cmd.assert().success();
+    assert_eq!(
+        "chain.auth\ttoken\nchain.cache\toff\nchain.zlib\ton\n",
+        values(&db_file)?
+    );
+    Ok(())
+}
+
+#[test]
+fn collation() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    std::fs::write(&db_file, "node.10\tten\nnode.2\ttwo\nProd.host\ta\n")?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["collate", "natural,case-folding"]);
+    cmd.assert().success();
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["add", "-k", "prod.port", "-v", "80"]);
+    cmd.assert().success();
+    assert_eq!(
+        "collation:natural,case-folding\n\
+         node.2\ttwo\n\
+         node.10\tten\n\
+         Prod.host\ta\n\
+         Prod.port\t80\n",
+        values(&db_file)?
+    );
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "PROD.HOST", "-o", "tsv"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Prod.host\ta\n"));
+
+    // Keys that would become the same are listed, the file stays as it is
+    std::fs::write(&db_file, "Prod.host\ta\nprod.host\tb\n")?;
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["collate", "case-insensitive"]);
+    cmd.assert().code(5).stderr(predicate::eq(
+        "Keys would become the same under the collation: Prod.host, prod.host\n",
+    ));
+    assert_eq!(
+        "Prod.host\ta\nprod.host\tb\n",
+        std::fs::read_to_string(&db_file)?
+    );
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["collate", "random"]);
+    cmd.assert().failure();
+    Ok(())
+}
+
+#[test]
+fn exec_script() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let script = dir.path().join("setup.kvs");
+    std::fs::write(
+        &script,
+        "# create the services\n\
+         add org.service.prod=up\n\
+         \n\
+         cd org.service\n\
+         add dev=down\n",
+    )?;
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.arg("exec").arg(&script);
+    cmd.assert().success();
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "org.service.dev", "--raw"]);
+    cmd.assert().success().stdout(predicate::eq("down\n"));
+
+    Ok(())
+}
+
+#[test]
+fn exec_script_failure() -> Result<(), Box<dyn Error>> {
+    let dir = tempdir()?;
+    let db_file = dir.path().join("kv.db");
+    let script = "add a=1\nremove -k missing\nadd b=2\n";
+
+    // Stops at the failing line and leaves the database untouched
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["exec", "-"])
+        .write_stdin(script);
+    cmd.assert()
+        .code(4)
+        .stderr(predicate::str::starts_with("line 2: "));
+    assert!(!db_file.exists());
+
+    // Runs every line, saves, and still reports the failure
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["-i", "--keep-going"])
+        .write_stdin(script);
+    cmd.assert().code(4);
+
+    let mut cmd = db_command(&db_file)?;
+    cmd.args(["get", "-k", "b", "--raw"]);
+    cmd.assert().success().stdout(predicate::eq("2\n"));
+
+    // Only failing lines leave the file as it is
+    std::fs::write(&db_file, "a\t1\n")?;
+    let mut cmd = assert_cmd::Command::cargo_bin("kv_db")?;
+    cmd.arg("-f")
+        .arg(&db_file)
+        .args(["exec", "-", "--keep-going"])
+        .write_stdin("remove -k missing\nadd a.b=2\nadd a=1 --if-absent\n");
+    cmd.assert().code(4);
+    assert_eq!("a\t1\n", std::fs::read_to_string(&db_file)?);
+
+    Ok(())
+}
+
+/// Lines of a database file without the checksum lines flush adds.
+fn values(db_file: &Path) -> Result<String, Box<dyn Error>> {
+    Ok(std::fs::read_to_string(db_file)?
+        .lines()
+        .filter(|line| !line.starts_with("checksum:") && *line != checksum::MARKER)
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
+fn db_command(db_file: &Path) -> Result<Command, Box<dyn Error>> {
+    let mut cmd = Command::cargo_bin("kv_db")?;
+    cmd.arg("-f").arg(db_file);
+    Ok(cmd)
+}
+
 fn construct_help() -> String {
     let mut output = String::new();
     output.push_str("Key Value Database ");
@@ -72,28 +1621,51 @@ fn construct_help() -> String {
     output.push_str("\n\n");
     output.push_str(env!("CARGO_PKG_AUTHORS"));
     output.push_str("\n\n");
+    output.push_str("A Command Line Key Value Store Database\n\nUSAGE:\n    kv_db");
+    output.push_str(std::env::consts::EXE_SUFFIX);
     output.push_str(
-        r"A Command Line Key Value Store Database
-
-USAGE:
-    kv_db.exe [FLAGS] [OPTIONS] [SUBCOMMAND]
+        r" [FLAGS] [OPTIONS] [SUBCOMMAND]
 
 FLAGS:
-    -h, --help           Print help information
-    -i, --interactive    Interactive Database mode
-    -V, --version        Print version information
+        --drop-skipped    Save even though the lines a lenient load skipped are lost
+    -e, --encrypted       Read and write the database file encrypted with a passphrase
+    -h, --help            Print help information
+    -i, --interactive     Interactive Database mode
+        --keep-going      Keep running a script after a failing line
+    -V, --version         Print version information
 
 OPTIONS:
-    -f, --file <db-file>...    Sets the db file for database
-    -l, --log <log>            Sets the level of logging to output, default is off [possible values:
-                               error, warn, info, debug, trace]
+    -f, --file <db-file>...          Sets the db file for database
+        --key-order <key-order>      Keep keys sorted, or in the order they were added, from now on
+                                     [possible values: sorted, insertion]
+    -l, --log <log>                  Sets the level of logging to output, default is off [possible
+                                     values: error, warn, info, debug, trace]
+        --load-mode <load-mode>      Fail on duplicate keys too, or skip the lines that do not load
+                                     [possible values: strict, normal, lenient]
+        --sign-key <sign-key>        Sign the database file on save with the key in this file
+        --verify-key <verify-key>    Refuse a database file not signed for this public key file
 
 SUBCOMMANDS:
-    add       Add new key value to database
-    get       Get value(s) from the database
-    help      Print this message or the help of the given subcommand(s)
-    print     Print Database to standard out
-    remove    Remove value from the database",
+    add             Add new key value to database
+    collate         Change how key segments compare, recorded in the database file
+    decrypt         Store the database file without encryption
+    diff            Show the differences between two database files or two keys
+    edit            Edit the values below a key in $EDITOR
+    encrypt         Encrypt the database file with a passphrase
+    exec            Run the interactive commands of a script file and save once at the end
+    fsck            Report every problem of the database file by line
+    get             Get value(s) from the database
+    help            Print this message or the help of the given subcommand(s)
+    keygen          Create a key pair to sign database files with
+    ls              List the direct children of a key
+    merge-driver    Three way merge for git, writes the result over the ours file
+    patch           Create or apply a patch of set, remove and move operations
+    print           Print Database to standard out
+    remove          Remove value from the database
+    search          Search keys and values with a regular expression
+    secret          Mark a key and the keys below it secret, or list the secret keys
+    tree            Print the database hierarchy as a tree
+    verify          Check the checksum of the database file and its signature",
     );
     output
 }
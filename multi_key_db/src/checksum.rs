@@ -0,0 +1,106 @@
+use crate::error::DBError;
+use sha2::{Digest, Sha256};
+
+type Result<T> = std::result::Result<T, DBError>;
+
+const CHECKSUM_SETTING: &str = "checksum:";
+/// Header line of files written with a checksum line, without the checksum
+/// line such a file was truncated.
+pub const MARKER: &str = "format:checksum";
+
+/// Appends the checksum line, the SHA-256 of everything before it.
+pub fn append(contents: &mut String) {
+    let checksum = hex::encode(Sha256::digest(contents.as_bytes()));
+    contents.push_str(CHECKSUM_SETTING);
+    contents.push_str(&checksum);
+    contents.push('\n');
+}
+
+/// True when contents hold a checksum line, lines added after it included.
+pub fn is_present(contents: &str) -> bool {
+    split(contents).is_some()
+}
+
+/// True for a checksum line, a key starting with checksum: has a tab.
+pub fn is_line(line: &str) -> bool {
+    line.starts_with(CHECKSUM_SETTING) && !line.contains('\t')
+}
+
+/// Number and text of the last checksum line.
+pub fn find(contents: &str) -> Option<(usize, &str)> {
+    let (body, _, _) = split(contents)?;
+    let number = body.lines().count() + 1;
+    contents.lines().nth(number - 1).map(|line| (number, line))
+}
+
+/// The contents before the checksum line. Fails when the checksum does not
+/// match, lines follow it, or it is missing from contents with the marker.
+/// Other contents without a checksum line are returned as they are.
+pub fn verify(contents: &str) -> Result<&str> {
+    match split(contents) {
+        Some((body, checksum, after)) => {
+            if hex::encode(Sha256::digest(body.as_bytes())) != checksum || !after.trim().is_empty()
+            {
+                return Err(DBError::ChecksumMismatch);
+            }
+            Ok(body)
+        }
+        None if contents.lines().any(|line| line == MARKER) => Err(DBError::ChecksumMismatch),
+        None => Ok(contents),
+    }
+}
+
+/// The contents before the checksum line, whether it matches or not.
+pub fn body(contents: &str) -> &str {
+    split(contents).map_or(contents, |(body, _, _)| body)
+}
+
+/// Body before the last checksum line, its checksum and the lines after it.
+fn split(contents: &str) -> Option<(&str, &str, &str)> {
+    let mut start = 0;
+    let mut found = None;
+    for line in contents.split_inclusive('\n') {
+        if is_line(line.trim_end()) {
+            found = Some((start, start + line.len()));
+        }
+        start += line.len();
+    }
+    let (start, end) = found?;
+    let checksum = contents[start..end]
+        .trim_end()
+        .strip_prefix(CHECKSUM_SETTING)?;
+    Some((&contents[..start], checksum, &contents[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::*;
+
+    #[test]
+    fn checksum_append_verify() {
+        let mut contents = String::from("db.host\tlocalhost\n");
+        append(&mut contents);
+        assert!(contents.starts_with("db.host\tlocalhost\nchecksum:"));
+        assert!(is_present(&contents));
+        assert_eq!("db.host\tlocalhost\n", verify(&contents).unwrap());
+
+        let changed = contents.replace("localhost", "example.com");
+        assert_eq!(DBError::ChecksumMismatch, verify(&changed).err().unwrap());
+        let truncated = contents.replacen("db.host\tlocalhost\n", "db.host\tlocal\n", 1);
+        assert_eq!(DBError::ChecksumMismatch, verify(&truncated).err().unwrap());
+
+        // Dropping the checksum line of a marked file is noticed
+        let marked = format!("{}\ndb.host\tlocalhost\n", MARKER);
+        assert_eq!(DBError::ChecksumMismatch, verify(&marked).err().unwrap());
+
+        // Lines added after the checksum line do not match it either
+        let added = format!("{}a.d\t3\n", contents);
+        assert!(is_present(&added));
+        assert_eq!(DBError::ChecksumMismatch, verify(&added).err().unwrap());
+        assert_eq!(Some((2, contents.lines().nth(1).unwrap())), find(&added));
+
+        // Files written by hand have no checksum
+        assert!(!is_present("checksum:key\tvalue\n"));
+        assert_eq!("a\tb\n", verify("a\tb\n").unwrap());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::error::KeyError;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// How the case of key segments counts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Case {
+    #[default]
+    Sensitive,
+    /// Segments differing in case are the same key, stored in lower case.
+    Insensitive,
+    /// Segments differing in case are the same key, which keeps the case
+    /// it was first written in.
+    Folding,
+}
+
+/// How key segments compare, for ordering and lookups.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Collation {
+    /// Runs of digits compare by their number, so 2 sorts before 10.
+    pub natural: bool,
+    pub case: Case,
+}
+
+/// Part of a segment as it compares, numbers sort before text.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Chunk {
+    /// Digits without leading zeros, after their count.
+    Number(usize, String),
+    Text(String),
+}
+
+impl Collation {
+    pub const NAMES: [&'static str; 4] = ["binary", "natural", "case-insensitive", "case-folding"];
+
+    pub fn is_binary(&self) -> bool {
+        *self == Collation::default()
+    }
+
+    /// True when segments differing only in case are the same key.
+    pub fn folds_case(&self) -> bool {
+        self.case != Case::Sensitive
+    }
+
+    /// Keys segment by segment, a key sorts right before the keys below it.
+    pub fn compare<K: ToString>(&self, a: &[K], b: &[K]) -> Ordering {
+        for (a, b) in a.iter().zip(b) {
+            let (a, b) = (a.to_string(), b.to_string());
+            // Segments that tie by their chunks still differ by their text
+            match self
+                .chunks(&a)
+                .cmp(&self.chunks(&b))
+                .then_with(|| a.cmp(&b))
+            {
+                Ordering::Equal => (),
+                ordering => return ordering,
+            }
+        }
+        a.len().cmp(&b.len())
+    }
+
+    /// True when the segments are the same key, natural ordering leaves
+    /// them apart.
+    pub fn same<K: ToString>(&self, a: &K, b: &K) -> bool {
+        self.fold(&a.to_string()) == self.fold(&b.to_string())
+    }
+
+    /// The segment as it is told apart from others, in lower case when the
+    /// collation folds case.
+    pub fn fold(&self, segment: &str) -> String {
+        match self.folds_case() {
+            true => segment.to_lowercase(),
+            false => segment.to_string(),
+        }
+    }
+
+    /// True when every segment of prefix is the same as the start of key.
+    pub fn starts_with<K: ToString>(&self, key: &[K], prefix: &[K]) -> bool {
+        key.len() >= prefix.len() && key.iter().zip(prefix).all(|(a, b)| self.same(a, b))
+    }
+
+    /// Segment as it compares under the collation.
+    pub(crate) fn chunks(&self, segment: &str) -> Vec<Chunk> {
+        let segment = self.fold(segment);
+        if !self.natural {
+            return vec![Chunk::Text(segment)];
+        }
+        let mut chunks = Vec::new();
+        let mut rest = segment.as_str();
+        while let Some(first) = rest.chars().next() {
+            let digits = first.is_ascii_digit();
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != digits)
+                .unwrap_or(rest.len());
+            let (run, next) = rest.split_at(end);
+            chunks.push(if digits {
+                let number = run.trim_start_matches('0');
+                Chunk::Number(number.len(), number.to_string())
+            } else {
+                Chunk::Text(run.to_string())
+            });
+            rest = next;
+        }
+        chunks
+    }
+}
+
+impl FromStr for Collation {
+    type Err = KeyError;
+
+    /// Names separated by commas, like natural,case-folding.
+    fn from_str(names: &str) -> std::result::Result<Self, Self::Err> {
+        let mut collation = Collation::default();
+        for name in names.split(',') {
+            match (name, collation.case) {
+                ("binary", _) => (),
+                ("natural", _) => collation.natural = true,
+                ("case-insensitive", Case::Sensitive) => collation.case = Case::Insensitive,
+                ("case-folding", Case::Sensitive) => collation.case = Case::Folding,
+                _ => return Err(KeyError::ParseError),
+            }
+        }
+        Ok(collation)
+    }
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names = Vec::new();
+        if self.natural {
+            names.push("natural");
+        }
+        match self.case {
+            Case::Sensitive => (),
+            Case::Insensitive => names.push("case-insensitive"),
+            Case::Folding => names.push("case-folding"),
+        }
+        if names.is_empty() {
+            names.push("binary");
+        }
+        write!(f, "{}", names.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collation::*;
+
+    #[test]
+    fn collation_from_str() {
+        assert_eq!(Ok(Collation::default()), Collation::from_str("binary"));
+        let collation = Collation::from_str("natural,case-folding").unwrap();
+        assert!(collation.natural);
+        assert_eq!(Case::Folding, collation.case);
+        assert_eq!("natural,case-folding", collation.to_string());
+        assert_eq!("binary", Collation::default().to_string());
+        assert_eq!(
+            Err(KeyError::ParseError),
+            Collation::from_str("case-folding,case-insensitive")
+        );
+        assert_eq!(Err(KeyError::ParseError), Collation::from_str("random"));
+    }
+
+    #[test]
+    fn collation_chunks() {
+        let natural = Collation::from_str("natural").unwrap();
+        assert!(natural.chunks("item2") < natural.chunks("item10"));
+        assert!(natural.chunks("2") < natural.chunks("a"));
+        assert_eq!(natural.chunks("v007"), natural.chunks("v7"));
+        assert!(natural.chunks("Prod") < natural.chunks("prod"));
+
+        let binary = Collation::default();
+        assert!(binary.chunks("item10") < binary.chunks("item2"));
+
+        let folding = Collation::from_str("case-folding").unwrap();
+        assert_eq!(folding.chunks("Prod"), folding.chunks("prod"));
+    }
+
+    #[test]
+    fn collation_compare() {
+        let natural = Collation::from_str("natural").unwrap();
+        let key = |key: &str| key.split('.').map(String::from).collect::<Vec<_>>();
+        assert_eq!(
+            Ordering::Less,
+            natural.compare(&key("item.2"), &key("item.10"))
+        );
+        assert_eq!(
+            Ordering::Less,
+            natural.compare(&key("item"), &key("item.2"))
+        );
+        // Ties by number still differ
+        assert_ne!(Ordering::Equal, natural.compare(&key("v02"), &key("v2")));
+        assert!(!natural.same(&"Prod", &"prod"));
+
+        let folding = Collation::from_str("natural,case-folding").unwrap();
+        assert!(folding.same(&"Prod", &"prod"));
+        // Natural ordering keeps leading zeros apart
+        assert!(!folding.same(&"v02", &"v2"));
+        assert!(folding.starts_with(&key("PROD.host.port"), &key("prod.Host")));
+        assert!(!folding.starts_with(&key("prod"), &key("prod.host")));
+    }
+}
@@ -1,29 +1,53 @@
+use crate::checksum;
+use crate::collation::{Case, Collation};
+use crate::diff::Change;
+#[cfg(feature = "encryption")]
+use crate::encryption;
 use crate::error::DBError;
-use crate::error::KeyError;
+use crate::fsck::{Fsck, Problem, ProblemKind};
 use crate::key::Key;
+use crate::layout::{KeyOrder, Layout};
+use crate::load::{LoadOptions, LoadReport};
+use crate::merge::{Conflict, MergeStrategy, Merged};
 use crate::node::NodeType;
+use crate::patch::{Operation, Patch};
+use crate::search::{SearchPattern, SearchScope};
 
+use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::str::FromStr;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{BufWriter, Read, Write};
 
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 
-#[cfg(any(feature = "cli-features"))]
-use cli_table::{
-    format::{Align, Justify, Padding},
-    print_stdout, Cell, Table,
-};
+#[cfg(feature = "cli-features")]
+use crate::render::{Entry, OutputFormat, Renderer};
 
 type Result<T> = std::result::Result<T, DBError>;
 const SPLIT_SETTING: &str = "split:";
+const SECRET_SETTING: &str = "secret:";
+const SEALED_SETTING: &str = "sealed:";
+const ORDER_SETTING: &str = "order:";
+const COLLATION_SETTING: &str = "collation:";
+
+// Opens the sealed secret value of a key
+type Open = dyn Fn(&str, &str) -> Result<String>;
+
+// Seals the value of a key
+type Seal = fn(&str, &str) -> Result<String>;
+
+// Writes values as they are
+const NO_SEALING: Option<(String, Seal)> = None;
+
+/// Line number, lines and the comments before an entry of a file.
+type FileEntry = (usize, String, Vec<String>);
 
 /// Database for MultiKey DB
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Database<K, V>
 where
     K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr,
@@ -35,6 +59,16 @@ where
     //                   -> BTree Of Children same as this
     pub map: BTreeMap<Key<K>, NodeType<K, V>>,
     divider: char,
+    // Keys whose values, and every value below them, are secret
+    secrets: BTreeSet<Key<K>>,
+    // Comments and key order of the file the database was loaded from
+    layout: Layout<K>,
+    key_order: KeyOrder,
+    // Keys of map in the order they were inserted, kept with KeyOrder::Insertion
+    insertion: Vec<Key<K>>,
+    collation: Collation,
+    // Keys of map by their folded segment, kept when the collation folds case
+    folded: BTreeMap<String, Key<K>>,
 }
 
 impl<K, V> Default for Database<K, V>
@@ -56,86 +90,743 @@ where
         Database {
             map: BTreeMap::new(),
             divider: '.', //default divider
+            secrets: BTreeSet::new(),
+            layout: Layout::default(),
+            key_order: KeyOrder::default(),
+            insertion: Vec::new(),
+            collation: Collation::default(),
+            folded: BTreeMap::new(),
         }
     }
 
+    /// An empty database keeping its keys in key_order.
+    pub fn with_key_order(key_order: KeyOrder) -> Database<K, V> {
+        let mut database = Database::new();
+        database.key_order = key_order;
+        database
+    }
+
     /// Creates a in memory database.
     /// Fill data from the file provided
+    /// Lines starting with a tab continue the value of the line before,
+    /// which is how flush writes values spanning several lines.
+    /// A file with sealed secret values needs new_from_encrypted_file.
     pub fn new_from_file<F>(file: &mut F) -> Result<Database<K, V>>
+    where
+        F: Read,
+    {
+        Database::load(file, &LoadOptions::default()).map(|(database, _)| database)
+    }
+
+    /// Like new_from_file, the mode of options decides which problems of the
+    /// file fail. The report holds the problems that did not.
+    pub fn load<F>(file: &mut F, options: &LoadOptions) -> Result<(Database<K, V>, LoadReport)>
+    where
+        F: Read,
+    {
+        Database::read_file(file, None, options)
+    }
+
+    /// Checks the file entry by entry instead of failing on the first problem.
+    /// Every entry that loads is kept, see Fsck.
+    pub fn fsck<F>(file: &mut F) -> Result<Fsck<K, V>>
+    where
+        F: Read,
+    {
+        Database::parse(file, None)
+    }
+
+    /// Like fsck for files new_from_encrypted_file reads.
+    #[cfg(feature = "encryption")]
+    pub fn fsck_encrypted<F>(file: &mut F, passphrase: &str) -> Result<Fsck<K, V>>
+    where
+        F: Read,
+    {
+        let contents = Database::<K, V>::decrypted(file, passphrase)?;
+        Database::parse(&mut contents.as_slice(), Some(passphrase))
+    }
+
+    /// Fails with the first problem of the file the mode fails on,
+    /// a checksum that does not match before any other.
+    fn read_file<F>(
+        file: &mut F,
+        passphrase: Option<&str>,
+        options: &LoadOptions,
+    ) -> Result<(Database<K, V>, LoadReport)>
+    where
+        F: Read,
+    {
+        let fsck = Database::parse(file, passphrase)?;
+        let failing: Vec<&Problem> = fsck
+            .problems
+            .iter()
+            .filter(|problem| options.mode.fails_on(problem))
+            .collect();
+        let problem = failing
+            .iter()
+            .find(|problem| problem.kind == ProblemKind::ChecksumMismatch)
+            .or_else(|| failing.iter().min_by_key(|problem| problem.failing_line()));
+        if let Some(problem) = problem {
+            error!("Load failed, {}", problem);
+            return Err(problem.error(fsck.database.divider));
+        }
+        for problem in &fsck.problems {
+            warn!("Load skipped {}", problem);
+        }
+
+        trace!("Map: {0:#?}", fsck.database.map);
+
+        debug!("DB Creation from file.");
+        Ok((
+            fsck.database,
+            LoadReport {
+                warnings: fsck.problems,
+            },
+        ))
+    }
+
+    /// Secret keys are listed before the values, followed by the header of
+    /// the key their values are sealed with, when they are sealed.
+    /// A checksum line at the end has to match the rest of the file.
+    /// Only errors reading the file fail, problems of entries are collected.
+    #[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
+    fn parse<F>(file: &mut F, passphrase: Option<&str>) -> Result<Fsck<K, V>>
     where
         F: Read,
     {
         let mut database = Database::new();
-        {
-            let mut settings_read = false;
-            let buff_reader = BufReader::new(file);
-            for line in BufRead::lines(buff_reader) {
-                let mut line = line?;
-                if line.is_empty() || line.as_str().starts_with('#') {
+        let mut problems = Vec::new();
+        #[cfg_attr(not(feature = "encryption"), allow(unused_mut))]
+        let mut open: Option<Box<Open>> = None;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        #[cfg(feature = "encryption")]
+        if encryption::is_encrypted(&bytes) {
+            return Err(DBError::PassphraseRequired);
+        }
+        let contents = String::from_utf8(bytes)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+        // Lines added after the checksum line load too, the mismatch is reported
+        match checksum::verify(&contents) {
+            Ok(_) => (),
+            Err(DBError::ChecksumMismatch) => {
+                let (line, text) =
+                    checksum::find(&contents).unwrap_or((contents.lines().count() + 1, ""));
+                problems.push(Problem {
+                    line,
+                    kind: ProblemKind::ChecksumMismatch,
+                    text: text.into(),
+                    comments: Vec::new(),
+                });
+            }
+            Err(error) => return Err(error),
+        };
+
+        let mut settings_read = false;
+        // Line number, lines and comments of the last entry, inserted once no
+        // continuation follows
+        let mut pending: Option<FileEntry> = None;
+        // Comments and blank lines since the last entry
+        let mut comments = Vec::new();
+        // Line number, lines and comments of the entry each key was inserted from
+        let mut inserted: BTreeMap<Key<K>, FileEntry> = BTreeMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let number = index + 1;
+            if line.starts_with('\t') {
+                match pending.as_mut() {
+                    Some((_, text, _)) => {
+                        text.push('\n');
+                        text.push_str(line);
+                    }
+                    None => problems.push(Problem {
+                        line: number,
+                        kind: ProblemKind::OrphanContinuation,
+                        text: line.into(),
+                        comments: Vec::new(),
+                    }),
+                }
+                continue;
+            }
+            if let Some(entry) = pending.take() {
+                problems.extend(database.parse_entry(entry, open.as_deref(), &mut inserted)?);
+            }
+            if line.is_empty() || line.starts_with('#') {
+                comments.push(line.to_string());
+                continue;
+            }
+            if !line.contains('\t') {
+                let problem = |kind| Problem {
+                    line: number,
+                    kind,
+                    text: line.into(),
+                    comments: Vec::new(),
+                };
+                if line == checksum::MARKER || checksum::is_line(line) {
+                    // Checked before
+                    continue;
+                }
+                if let Some(key) = line.strip_prefix(SECRET_SETTING) {
+                    match Key::new_from_str(key, database.divider) {
+                        Ok(key) => {
+                            database.secrets.insert(key);
+                        }
+                        Err(_) => problems.push(problem(ProblemKind::BadKey)),
+                    }
                     continue;
                 }
-                if !settings_read && line.contains(SPLIT_SETTING) {
-                    if line.len() == 7 {
-                        database.divider = line.pop().unwrap();
-                        settings_read = true;
+                if let Some(header) = line.strip_prefix(SEALED_SETTING) {
+                    #[cfg(feature = "encryption")]
+                    {
+                        let passphrase = passphrase.ok_or(DBError::PassphraseRequired)?;
+                        let cipher = encryption::ValueCipher::from_header(passphrase, header)?;
+                        open = Some(Box::new(move |key, value| cipher.open(key, value)));
                         continue;
-                    } else {
-                        return Err(DBError::CorruptDBFile);
                     }
+                    // Sealed values only open with the encryption feature
+                    #[cfg(not(feature = "encryption"))]
+                    return Err(DBError::PassphraseRequired);
+                }
+                if let Some(collation) = line.strip_prefix(COLLATION_SETTING) {
+                    match Collation::from_str(collation) {
+                        Ok(collation) => database.set_collation(collation)?,
+                        Err(_) => problems.push(problem(ProblemKind::BadCollationHeader)),
+                    }
+                    continue;
                 }
-                let (key, value) = line.split_once("\t").ok_or(DBError::CorruptDBFile)?;
-                match V::from_str(value) {
-                    Ok(parsed_value) => {
-                        database.insert(Key::new_from_str(key, database.divider)?, parsed_value)?
+                if let Some(key_order) = line.strip_prefix(ORDER_SETTING) {
+                    match KeyOrder::from_str(key_order) {
+                        Ok(key_order) => database.set_key_order(key_order),
+                        Err(_) => problems.push(problem(ProblemKind::BadOrderHeader)),
                     }
-                    Err(_) => {
-                        error!("Parse error, value of V: {0:#?}", value);
-                        return Err(KeyError::ParseError.into());
+                    continue;
+                }
+                if let Some(divider) = line.strip_prefix(SPLIT_SETTING) {
+                    let mut chars = divider.chars();
+                    match (settings_read, chars.next(), chars.next()) {
+                        (false, Some(divider), None) => {
+                            database.divider = divider;
+                            settings_read = true;
+                        }
+                        _ => problems.push(problem(ProblemKind::BadSplitHeader)),
                     }
+                    continue;
                 }
             }
+            pending = Some((number, line.into(), std::mem::take(&mut comments)));
         }
+        if let Some(entry) = pending {
+            problems.extend(database.parse_entry(entry, open.as_deref(), &mut inserted)?);
+        }
+        database.layout.set_footer(comments);
+        problems.sort_by_key(|problem| problem.line);
+        Ok(Fsck { database, problems })
+    }
 
-        trace!("Map: {0:#?}", database.map);
-
-        debug!("DB Creation from file.");
-        Ok(database)
+    /// Inserts the entry, a key and its value with continuation lines, and
+    /// keeps the comments before it. The problem is the entry's, or that of
+    /// the entry it replaces, and keeps its comments.
+    fn parse_entry(
+        &mut self,
+        (line, text, comments): FileEntry,
+        open: Option<&Open>,
+        inserted: &mut BTreeMap<Key<K>, FileEntry>,
+    ) -> Result<Option<Problem>> {
+        let problem = |kind| {
+            Ok(Some(Problem {
+                line,
+                kind,
+                text: text.clone(),
+                comments: comments.clone(),
+            }))
+        };
+        let head = text.lines().next().unwrap_or_default();
+        let (written, value) = match head.split_once('\t') {
+            // Continuation lines lose the tab they start with
+            Some((key, _)) => (key, text[key.len() + 1..].replace("\n\t", "\n")),
+            None => return problem(ProblemKind::MalformedLine),
+        };
+        let key = match Key::new_from_str(written, self.divider) {
+            Ok(key) => self.stored(&key),
+            Err(_) => return problem(ProblemKind::BadKey),
+        };
+        let value = match open {
+            // Sealed for the key as it is written
+            Some(open) if self.is_secret(&key) => match open(written, &value) {
+                Ok(value) => value,
+                Err(DBError::CorruptDBFile { .. }) => return problem(ProblemKind::BadValue),
+                Err(error) => return Err(error),
+            },
+            _ => value,
+        };
+        let value = match V::from_str(&value) {
+            Ok(value) => value,
+            Err(_) => {
+                error!("Parse error, value of V: {0:#?}", value);
+                return problem(ProblemKind::BadValue);
+            }
+        };
+        match self.insert(key.clone(), value) {
+            Ok(()) => self.layout.push(key.clone(), comments.clone()),
+            Err(DBError::InsertValueToDirectory { existing, .. }) => {
+                return problem(ProblemKind::ValueOnParent {
+                    existing: existing.to_string(self.divider),
+                })
+            }
+            Err(DBError::MultiKeyExtendValueKey { existing, .. }) => {
+                return problem(ProblemKind::KeyBelowValue {
+                    existing: existing.to_string(self.divider),
+                })
+            }
+            Err(_) => return problem(ProblemKind::BadKey),
+        }
+        Ok(inserted
+            .insert(key, (line, text, comments))
+            .map(|(replaced, text, comments)| Problem {
+                line: replaced,
+                kind: ProblemKind::DuplicateKey { replaced_on: line },
+                text,
+                comments,
+            }))
     }
 
     pub fn is_empty(&self) -> bool {
         self.map.is_empty()
     }
 
+    pub fn divider(&self) -> char {
+        self.divider
+    }
+
+    pub fn layout(&self) -> &Layout<K> {
+        &self.layout
+    }
+
+    pub fn key_order(&self) -> KeyOrder {
+        self.key_order
+    }
+
+    /// Changes the order of the children of every parent. Keys kept sorted
+    /// so far count as inserted in that order, going back to sorted also
+    /// drops the order of the loaded file.
+    pub fn set_key_order(&mut self, key_order: KeyOrder) {
+        if self.key_order == key_order {
+            return;
+        }
+        self.key_order = key_order;
+        self.insertion = match key_order {
+            KeyOrder::Sorted => {
+                self.layout.clear_order();
+                Vec::new()
+            }
+            KeyOrder::Insertion => self.map.keys().cloned().collect(),
+        };
+        for node in self.map.values_mut() {
+            if let NodeType::Parent(parent) = node {
+                parent.set_key_order(key_order);
+            }
+        }
+    }
+
+    pub fn collation(&self) -> Collation {
+        self.collation
+    }
+
+    /// Changes how key segments compare, and forgets the order of the loaded
+    /// file. Fails without changes when keys would become the same, listing
+    /// every such pair.
+    pub fn set_collation(&mut self, collation: Collation) -> Result<()> {
+        if self.collation == collation {
+            return Ok(());
+        }
+        let collisions = self.collisions(collation);
+        if !collisions.is_empty() {
+            return Err(DBError::CollationConflict {
+                keys: collisions
+                    .into_iter()
+                    .flat_map(|(first, second)| [first.to_string_key(), second.to_string_key()])
+                    .collect(),
+                divider: self.divider,
+            });
+        }
+        let mut database = self.nested();
+        database.collation = collation;
+        database.divider = self.divider;
+        let mut old = std::mem::replace(self, database);
+        let secrets = std::mem::take(&mut old.secrets);
+        let mut layout = std::mem::take(&mut old.layout);
+        for (key, value) in old.into_pairs() {
+            self.insert(key, value)?;
+        }
+        for secret in secrets {
+            self.mark_secret(secret);
+        }
+        layout.collate(|key| self.stored(key));
+        self.layout = layout;
+        Ok(())
+    }
+
+    /// Pairs of keys that are the same under collation, or one below the
+    /// value of the other.
+    fn collisions(&self, collation: Collation) -> Vec<(Key<K>, Key<K>)> {
+        if !collation.folds_case() {
+            return Vec::new();
+        }
+        let keys: Vec<(Key<K>, Vec<String>)> = self
+            .generate_key_value_pairs()
+            .into_iter()
+            .map(|(key, _)| {
+                let folded = key
+                    .segments()
+                    .iter()
+                    .map(|segment| collation.fold(&segment.to_string()))
+                    .collect();
+                (key, folded)
+            })
+            .collect();
+        let mut collisions = Vec::new();
+        // The first key of every folded key
+        let mut values: BTreeMap<&[String], &Key<K>> = BTreeMap::new();
+        for (key, folded) in &keys {
+            match values.get(folded.as_slice()) {
+                Some(first) => collisions.push(((*first).clone(), key.clone())),
+                None => {
+                    values.insert(folded, key);
+                }
+            }
+        }
+        for (key, folded) in &keys {
+            for depth in 1..folded.len() {
+                if let Some(value) = values.get(&folded[..depth]) {
+                    collisions.push(((*value).clone(), key.clone()));
+                }
+            }
+        }
+        collisions
+    }
+
+    /// Orders keys under the collation of the database.
+    pub fn compare(&self, a: &Key<K>, b: &Key<K>) -> Ordering {
+        if self.collation.is_binary() {
+            return a.cmp(b);
+        }
+        self.collation.compare(a.segments(), b.segments())
+    }
+
+    /// The key as the database stores it. When case folds, segments that
+    /// exist are written the way they are stored and new segments of a case
+    /// insensitive database in lower case.
+    pub fn stored(&self, key: &Key<K>) -> Key<K> {
+        if !self.collation.folds_case() {
+            return key.clone();
+        }
+        let root = match key.root() {
+            Ok(root) => root,
+            Err(_) => return key.clone(),
+        };
+        let found = self.map.get_key_value(&root).or_else(|| {
+            self.folded
+                .get(&self.folded(&root))
+                .and_then(|segment| self.map.get_key_value(segment))
+        });
+        let (mut stored, node) = match found {
+            Some((segment, node)) => (segment.clone(), Some(node)),
+            None => (self.new_segments(&root), None),
+        };
+        if let Ok(rest) = key.remove_root() {
+            match node {
+                Some(NodeType::Parent(parent)) => stored.add(&parent.stored(&rest)),
+                _ => stored.add(&self.new_segments(&rest)),
+            }
+        }
+        stored
+    }
+
+    /// The node at a single segment, found through folded when case folds.
+    fn node(&self, segment: &Key<K>) -> Option<&NodeType<K, V>> {
+        self.map
+            .get(segment)
+            .or_else(|| match self.collation.folds_case() {
+                true => self
+                    .folded
+                    .get(&self.folded(segment))
+                    .and_then(|segment| self.map.get(segment)),
+                false => None,
+            })
+    }
+
+    /// The folded text of a segment, its key in folded.
+    fn folded(&self, segment: &Key<K>) -> String {
+        self.collation.fold(&segment.segments()[0].to_string())
+    }
+
+    /// Segments not stored yet, in lower case when the database is case
+    /// insensitive and they still parse.
+    fn new_segments(&self, key: &Key<K>) -> Key<K> {
+        if self.collation.case != Case::Insensitive {
+            return key.clone();
+        }
+        let segments = key
+            .segments()
+            .iter()
+            .map(|segment| {
+                K::from_str(&segment.to_string().to_lowercase()).unwrap_or_else(|_| segment.clone())
+            })
+            .collect();
+        Key::new_from_vec(segments).unwrap_or_else(|_| key.clone())
+    }
+
+    /// An empty database with the divider, key order and collation of this
+    /// one, like the ones holding the keys below a segment.
+    pub fn nested(&self) -> Database<K, V> {
+        let mut database = Database::with_key_order(self.key_order);
+        database.divider = self.divider;
+        database.collation = self.collation;
+        database
+    }
+
+    /// Direct children with their nodes, in the key order of the database.
+    /// Children put into map directly follow the inserted ones, sorted.
+    pub fn nodes(&self) -> Vec<(&Key<K>, &NodeType<K, V>)> {
+        match self.key_order {
+            KeyOrder::Sorted => {
+                let mut nodes: Vec<_> = self.map.iter().collect();
+                if !self.collation.is_binary() {
+                    nodes.sort_by(|(a, _), (b, _)| self.compare(a, b));
+                }
+                nodes
+            }
+            KeyOrder::Insertion => {
+                let inserted: BTreeSet<&Key<K>> = self.insertion.iter().collect();
+                let mut nodes: Vec<_> = self
+                    .insertion
+                    .iter()
+                    .filter_map(|segment| self.map.get_key_value(segment))
+                    .collect();
+                nodes.extend(
+                    self.map
+                        .iter()
+                        .filter(|(segment, _)| !inserted.contains(segment)),
+                );
+                nodes
+            }
+        }
+    }
+
+    /// Marks key and every key below it as secret.
+    pub fn mark_secret(&mut self, key: Key<K>) {
+        self.secrets.insert(self.stored(&key));
+    }
+
+    /// Removes the mark of key, true when key was marked.
+    /// Keys below a marked parent stay secret.
+    pub fn unmark_secret(&mut self, key: &Key<K>) -> bool {
+        let collation = self.collation;
+        let marked = self.secrets.len();
+        self.secrets.retain(|secret| {
+            secret.size() != key.size() || !collation.starts_with(secret.segments(), key.segments())
+        });
+        marked != self.secrets.len()
+    }
+
+    /// True when key or one of its parents is marked secret.
+    pub fn is_secret(&self, key: &Key<K>) -> bool {
+        self.secrets.iter().any(|secret| {
+            self.collation
+                .starts_with(key.segments(), secret.segments())
+        })
+    }
+
+    pub fn secrets(&self) -> &BTreeSet<Key<K>> {
+        &self.secrets
+    }
+
+    /// Copy with every secret value replaced by mask, for output.
+    pub fn masked(&self, mask: &V) -> Database<K, V>
+    where
+        V: Clone,
+    {
+        let mut masked = self.clone();
+        for (key, _) in self.generate_key_value_pairs() {
+            if self.is_secret(&key) {
+                // Replaces a value, which can not fail
+                let _ = masked.insert(key, mask.clone());
+            }
+        }
+        masked
+    }
+
+    /// Finds the node stored at key, either a value or a parent.
+    pub fn get_node(&self, key: &Key<K>) -> Option<&NodeType<K, V>> {
+        let key = self.stored(key);
+        let node = self.map.get(&key.root().ok()?)?;
+        if !key.is_multi_key() {
+            return Some(node);
+        }
+        node.get_parent_ref()?.get_node(&key.remove_root().ok()?)
+    }
+
+    /// Direct children of key, as single segment keys with their node.
+    /// Empty when key is missing or holds a value.
+    pub fn children(&self, key: &Key<K>) -> Vec<(Key<K>, &NodeType<K, V>)> {
+        match self.get_node(key) {
+            Some(NodeType::Parent(parent)) => parent
+                .nodes()
+                .into_iter()
+                .map(|(segment, node)| (segment.clone(), node))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Full keys of the direct children of key that hold a value.
+    pub fn keys(&self, key: &Key<K>) -> Vec<Key<K>> {
+        let key = self.stored(key);
+        self.children(&key)
+            .into_iter()
+            .filter(|(_, node)| node.is_value())
+            .map(|(segment, _)| {
+                let mut child = key.clone();
+                child.add(&segment);
+                child
+            })
+            .collect()
+    }
+
+    /// Walks every key value pair and returns the ones where pattern matches
+    /// the full key, the value, or either depending on scope.
+    pub fn search<P>(&self, pattern: &P, scope: SearchScope) -> Vec<(Key<K>, &V)>
+    where
+        P: SearchPattern + ?Sized,
+    {
+        self.generate_key_value_pairs()
+            .into_iter()
+            .filter(|(key, value)| {
+                let key_match = || pattern.is_match(&key.to_string(self.divider));
+                let value_match = || pattern.is_match(&value.to_string());
+                match scope {
+                    SearchScope::Keys => key_match(),
+                    SearchScope::Values => value_match(),
+                    SearchScope::Both => key_match() || value_match(),
+                }
+            })
+            .collect()
+    }
+
+    /// Number of values stored anywhere below this database.
+    pub fn value_count(&self) -> usize {
+        self.map
+            .values()
+            .map(|node| match node {
+                NodeType::Parent(parent) => parent.value_count(),
+                NodeType::Value(_) => 1,
+            })
+            .sum()
+    }
+
     pub fn insert(&mut self, key: Key<K>, value: V) -> Result<()> {
+        let key = self.stored(&key);
         trace!("Insert, Key: {0:#?} Value: {1:#?}", key, value);
 
-        if key.is_multi_key() {
-            if let Some(node) = self.map.get_mut(&key.root()?) {
+        let result = self.insert_node(&key, 0, value);
+        trace!("DB: {0:#?}", self);
+        result
+    }
+
+    /// Inserts below the first depth segments of key, the path to this
+    /// database. Nothing is changed when the insert fails.
+    fn insert_node(&mut self, key: &Key<K>, depth: usize, value: V) -> Result<()> {
+        let segment = Key::new_from_vec(vec![key.segments()[depth].clone()])?;
+        if depth + 1 < key.size() {
+            if let Some(node) = self.map.get_mut(&segment) {
                 match node {
-                    NodeType::Parent(parent) => parent.insert(key.remove_root()?, value)?,
-                    NodeType::Value(_) => return Err(DBError::MultiKeyExtendValueKey),
+                    NodeType::Parent(parent) => parent.insert_node(key, depth + 1, value)?,
+                    NodeType::Value(_) => {
+                        return Err(DBError::MultiKeyExtendValueKey {
+                            key: key.to_string_key(),
+                            existing: Key::new_from_vec(key.segments()[..=depth].to_vec())?
+                                .to_string_key(),
+                            divider: self.divider,
+                            line: None,
+                        })
+                    }
                 }
             } else {
-                let mut parent = Database::new();
-                parent.insert(key.remove_root()?, value)?;
-                self.map.insert(key.root()?, NodeType::Parent(parent));
+                let mut parent = self.nested();
+                parent.insert_node(key, depth + 1, value)?;
+                self.insert_child(segment, NodeType::Parent(parent));
             }
-        } else if let Some(node) = self.map.get_mut(&key.root()?) {
+        } else if let Some(node) = self.map.get_mut(&segment) {
             match node {
-                NodeType::Parent(_) => return Err(DBError::InsertValueToDirectory),
+                NodeType::Parent(parent) => {
+                    // The first value below the key is in its way
+                    let mut existing = key.clone();
+                    if let Some((first, _)) = parent.generate_key_value_pairs().first() {
+                        existing.add(first);
+                    }
+                    return Err(DBError::InsertValueToDirectory {
+                        key: key.to_string_key(),
+                        existing: existing.to_string_key(),
+                        divider: self.divider,
+                        line: None,
+                    });
+                }
                 NodeType::Value(_) => {
-                    self.map.insert(key, NodeType::Value(value));
+                    self.map.insert(segment, NodeType::Value(value));
                 }
             }
         } else {
-            self.map.insert(key, NodeType::Value(value));
+            self.insert_child(segment, NodeType::Value(value));
         }
-
-        trace!("DB: {0:#?}", self);
         Ok(())
     }
 
+    /// Adds a new direct child.
+    fn insert_child(&mut self, segment: Key<K>, node: NodeType<K, V>) {
+        if self.key_order == KeyOrder::Insertion {
+            self.insertion.push(segment.clone());
+        }
+        if self.collation.folds_case() {
+            self.folded.insert(self.folded(&segment), segment.clone());
+        }
+        self.map.insert(segment, node);
+    }
+
+    /// Inserts the value only when the key does not already hold a value.
+    pub fn insert_if_absent(&mut self, key: Key<K>, value: V) -> Result<()> {
+        if self.value_at(&key).is_some() {
+            return Err(DBError::ConditionFailed);
+        }
+        self.insert(key, value)
+    }
+
+    /// Replaces the value only when the key already holds a value.
+    pub fn update_if_present(&mut self, key: Key<K>, value: V) -> Result<()> {
+        if self.value_at(&key).is_none() {
+            return Err(DBError::ConditionFailed);
+        }
+        self.insert(key, value)
+    }
+
+    /// Writes the new value only when the current value matches expected.
+    /// An expected of None means the key must not hold a value.
+    pub fn compare_and_swap(&mut self, key: &Key<K>, expected: Option<&V>, new: V) -> Result<()>
+    where
+        V: PartialEq,
+    {
+        if self.value_at(key) != expected {
+            return Err(DBError::ConditionFailed);
+        }
+        self.insert(key.clone(), new)
+    }
+
+    /// The value of key itself. Unlike get, a key below a value does not
+    /// find that value.
+    fn value_at(&self, key: &Key<K>) -> Option<&V> {
+        self.get_node(key).and_then(NodeType::get_value_ref)
+    }
+
     pub fn get(&self, key: &Key<K>) -> Option<&V> {
+        let key = self.stored(key);
         if let Some(value) = self.map.get(&key.root().unwrap()) {
             match value {
                 NodeType::Parent(parent) => match key.remove_root() {
@@ -150,20 +841,350 @@ where
     }
 
     pub fn remove(&mut self, key: &Key<K>) -> Option<V> {
+        let key = self.stored(key);
+        let value = self.remove_node(&key);
+        // Only the top database keeps a layout, of full keys
+        if value.is_some() {
+            self.layout.remove(&key);
+        }
+        value
+    }
+
+    fn remove_node(&mut self, key: &Key<K>) -> Option<V> {
         if key.is_multi_key() {
             if let Some(NodeType::Parent(parent)) = self.map.get_mut(&key.root().unwrap()) {
-                return parent.remove(&key.remove_root().unwrap());
+                return parent.remove_node(&key.remove_root().unwrap());
             }
         } else {
             let result = self.map.get(key);
             if result.is_some() && result.unwrap().is_value() {
+                self.insertion.retain(|segment| segment != key);
+                self.folded.remove(&self.folded(key));
                 return self.map.remove(key).unwrap().get_value();
             }
         }
         None
     }
 
+    /// Every difference between the values of self and other, sorted by key.
+    /// A type change comes right before the values below its parent.
+    pub fn diff(&self, other: &Database<K, V>) -> Vec<Change<K, V>>
+    where
+        V: Clone + PartialEq,
+    {
+        let mut changes = Vec::new();
+        self.diff_into(other, None, &mut changes);
+        changes
+    }
+
+    fn diff_into(
+        &self,
+        other: &Database<K, V>,
+        prefix: Option<&Key<K>>,
+        changes: &mut Vec<Change<K, V>>,
+    ) where
+        V: Clone + PartialEq,
+    {
+        // Segments of other by their folded text, to find them like self does
+        let folded: BTreeMap<String, &Key<K>> = match self.collation.folds_case() {
+            true => other
+                .map
+                .keys()
+                .map(|segment| (self.folded(segment), segment))
+                .collect(),
+            false => BTreeMap::new(),
+        };
+        let matching = |segment: &Key<K>| {
+            other.map.get(segment).or_else(|| {
+                folded
+                    .get(&self.folded(segment))
+                    .and_then(|segment| other.map.get(*segment))
+            })
+        };
+        let segments: BTreeSet<&Key<K>> = self
+            .map
+            .keys()
+            .chain(
+                other
+                    .map
+                    .keys()
+                    .filter(|segment| self.node(segment).is_none()),
+            )
+            .collect();
+        for segment in segments {
+            let key = match prefix {
+                Some(prefix) => {
+                    let mut key = prefix.clone();
+                    key.add(segment);
+                    key
+                }
+                None => segment.clone(),
+            };
+            match (self.node(segment), matching(segment)) {
+                (Some(NodeType::Value(before)), Some(NodeType::Value(after))) => {
+                    if before != after {
+                        changes.push(Change::Changed {
+                            key,
+                            before: before.clone(),
+                            after: after.clone(),
+                        });
+                    }
+                }
+                (Some(NodeType::Parent(before)), Some(NodeType::Parent(after))) => {
+                    before.diff_into(after, Some(&key), changes)
+                }
+                (Some(NodeType::Value(before)), Some(NodeType::Parent(after))) => {
+                    changes.push(Change::TypeChanged {
+                        key: key.clone(),
+                        before: Some(before.clone()),
+                        after: None,
+                    });
+                    Database::new().diff_into(after, Some(&key), changes);
+                }
+                (Some(NodeType::Parent(before)), Some(NodeType::Value(after))) => {
+                    changes.push(Change::TypeChanged {
+                        key: key.clone(),
+                        before: None,
+                        after: Some(after.clone()),
+                    });
+                    before.diff_into(&Database::new(), Some(&key), changes);
+                }
+                (Some(NodeType::Value(value)), None) => changes.push(Change::Removed {
+                    key,
+                    value: value.clone(),
+                }),
+                (None, Some(NodeType::Value(value))) => changes.push(Change::Added {
+                    key,
+                    value: value.clone(),
+                }),
+                (Some(NodeType::Parent(before)), None) => {
+                    before.diff_into(&Database::new(), Some(&key), changes)
+                }
+                (None, Some(NodeType::Parent(after))) => {
+                    Database::new().diff_into(after, Some(&key), changes)
+                }
+                (None, None) => (),
+            }
+        }
+    }
+
+    /// Three way merge of the changes ours and theirs made to base.
+    /// A key changed on one side takes that change, a key both sides changed
+    /// differently is a conflict decided by strategy. When one side holds a
+    /// value where the other holds values below it, the whole subtree is
+    /// taken from one side. The result uses the divider of ours.
+    pub fn merge(
+        base: &Database<K, V>,
+        ours: &Database<K, V>,
+        theirs: &Database<K, V>,
+        strategy: MergeStrategy,
+    ) -> Result<Merged<K, V>>
+    where
+        V: Clone + PartialEq,
+    {
+        let base_values = ours.collated_values(base);
+        let our_values = ours.collated_values(ours);
+        let their_values = ours.collated_values(theirs);
+        let keys: BTreeSet<&Vec<String>> = base_values
+            .keys()
+            .chain(our_values.keys())
+            .chain(their_values.keys())
+            .collect();
+
+        let mut merged: BTreeMap<Vec<String>, (Key<K>, V)> = BTreeMap::new();
+        let mut conflicts = Vec::new();
+        for collated in keys {
+            let (base_entry, our_entry, their_entry) = (
+                base_values.get(collated),
+                our_values.get(collated),
+                their_values.get(collated),
+            );
+            // Ours decides how a key is written, then theirs
+            let key = our_entry
+                .or(their_entry)
+                .or(base_entry)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            let base_value = base_entry.map(|(_, value)| *value);
+            let our_value = our_entry.map(|(_, value)| *value);
+            let their_value = their_entry.map(|(_, value)| *value);
+            let value = if our_value == their_value || their_value == base_value {
+                our_value
+            } else if our_value == base_value {
+                their_value
+            } else {
+                conflicts.push(Conflict::Value {
+                    key: key.clone(),
+                    base: base_value.cloned(),
+                    ours: our_value.cloned(),
+                    theirs: their_value.cloned(),
+                });
+                match strategy {
+                    MergeStrategy::Ours => our_value,
+                    MergeStrategy::Theirs => their_value,
+                    MergeStrategy::Fail => base_value,
+                }
+            };
+            if let Some(value) = value {
+                merged.insert(collated.clone(), (key, value.clone()));
+            }
+        }
+
+        // Values below a key sort right after it, so a value directly
+        // followed by one of its descendants is a value and a parent at once.
+        while let Some((collated, key)) = merged
+            .iter()
+            .zip(merged.iter().skip(1))
+            .find(|((collated, _), (next, _))| next.starts_with(collated))
+            .map(|((collated, (key, _)), _)| (collated.clone(), key.clone()))
+        {
+            merged.retain(|merged_key, _| !merged_key.starts_with(&collated));
+            let side = match strategy {
+                MergeStrategy::Ours => &our_values,
+                MergeStrategy::Theirs => &their_values,
+                MergeStrategy::Fail => &base_values,
+            };
+            for (side_key, (key, value)) in side.range(collated.clone()..) {
+                if !side_key.starts_with(&collated) {
+                    break;
+                }
+                merged.insert(side_key.clone(), (key.clone(), (*value).clone()));
+            }
+            conflicts.push(Conflict::Structure { key });
+        }
+
+        let mut database = ours.nested();
+        database.secrets = ours.secrets.union(&theirs.secrets).cloned().collect();
+        database.layout = ours.layout.clone();
+        // The keys of ours first, in the order of ours
+        for (key, _) in ours.generate_key_value_pairs() {
+            if let Some((key, value)) = merged.remove(&ours.collated(&key)) {
+                database.insert(key, value)?;
+            }
+        }
+        for (key, value) in merged.into_values() {
+            database.insert(key, value)?;
+        }
+        conflicts.sort_by(|a, b| a.key().cmp(b.key()));
+        Ok(Merged {
+            database,
+            conflicts,
+        })
+    }
+
+    /// The segments of key as the collation of this database compares them.
+    fn collated(&self, key: &Key<K>) -> Vec<String> {
+        key.segments()
+            .iter()
+            .map(|segment| self.collation.fold(&segment.to_string()))
+            .collect()
+    }
+
+    /// Every value of database with its key, by the collated key.
+    fn collated_values<'a>(
+        &self,
+        database: &'a Database<K, V>,
+    ) -> BTreeMap<Vec<String>, (Key<K>, &'a V)> {
+        database
+            .generate_key_value_pairs()
+            .into_iter()
+            .map(|(key, value)| (self.collated(&key), (key, value)))
+            .collect()
+    }
+
+    /// Operations of patch whose expected values do not match this database.
+    pub fn check_patch<'a>(&self, patch: &'a Patch<K, V>) -> Vec<&'a Operation<K, V>>
+    where
+        V: PartialEq,
+    {
+        patch
+            .operations
+            .iter()
+            .filter(|operation| {
+                let value = |key| self.value_at(key);
+                match operation {
+                    Operation::Set { key, old, .. } => value(key) != old.as_ref(),
+                    Operation::Remove { key, old } => value(key) != Some(old),
+                    Operation::Move {
+                        from,
+                        to,
+                        value: moved,
+                    } => value(from) != Some(moved) || value(to).is_some(),
+                }
+            })
+            .collect()
+    }
+
+    /// Applies every operation of patch, or none of them when an expected
+    /// value does not match or a value and a parent would overlap.
+    /// Removes and the sources of moves go first, so a value can become a parent.
+    pub fn apply_patch(&mut self, patch: &Patch<K, V>) -> Result<()>
+    where
+        V: Clone + PartialEq,
+    {
+        if !self.check_patch(patch).is_empty() {
+            return Err(DBError::ConditionFailed);
+        }
+        let mut patched = self.clone();
+        for operation in &patch.operations {
+            match operation {
+                Operation::Remove { key, .. } | Operation::Move { from: key, .. } => {
+                    patched.remove(key);
+                    patched.prune_parents(key);
+                }
+                Operation::Set { .. } => (),
+            }
+        }
+        for operation in &patch.operations {
+            match operation {
+                Operation::Set { key, value, .. } | Operation::Move { to: key, value, .. } => {
+                    patched.insert(key.clone(), value.clone())?
+                }
+                Operation::Remove { .. } => (),
+            }
+        }
+        *self = patched;
+        Ok(())
+    }
+
+    /// Drops parents left without any value, remove keeps them.
+    pub fn prune(&mut self) {
+        self.map.retain(|_, node| match node {
+            NodeType::Parent(parent) => {
+                parent.prune();
+                !parent.is_empty()
+            }
+            NodeType::Value(_) => true,
+        });
+        let map = &self.map;
+        self.insertion.retain(|segment| map.contains_key(segment));
+        self.folded.retain(|_, segment| map.contains_key(segment));
+    }
+
+    /// Drops the parents of key left without any value, from the deepest
+    /// up. Other empty parents are kept, unlike with prune.
+    pub fn prune_parents(&mut self, key: &Key<K>) {
+        let key = self.stored(key);
+        self.prune_path(&key);
+    }
+
+    fn prune_path(&mut self, key: &Key<K>) {
+        let (root, rest) = match (key.root(), key.remove_root()) {
+            (Ok(root), Ok(rest)) => (root, rest),
+            _ => return,
+        };
+        if let Some(NodeType::Parent(parent)) = self.map.get_mut(&root) {
+            parent.prune_path(&rest);
+            if parent.is_empty() {
+                self.map.remove(&root);
+                self.insertion.retain(|segment| *segment != root);
+                self.folded.remove(&self.folded(&root));
+            }
+        }
+    }
+
     pub fn get_values(&self, key: &Key<K>) -> Vec<(Key<K>, &V)> {
+        let key = &self.stored(key);
         if key.is_multi_key() {
             if let Some(NodeType::Parent(parent)) = self.map.get(&key.root().unwrap()) {
                 let temps = parent.get_values(&key.remove_root().unwrap());
@@ -193,23 +1214,172 @@ where
         Vec::new()
     }
 
-    pub fn flush<F>(&mut self, file: &mut F) -> Result<()>
+    /// Like new_from_file, decrypting the file first when it is encrypted
+    /// and opening sealed secret values. Plain files are read as they are.
+    #[cfg(feature = "encryption")]
+    pub fn new_from_encrypted_file<F>(file: &mut F, passphrase: &str) -> Result<Database<K, V>>
     where
-        F: Write,
+        F: Read,
     {
-        let mut contents = String::new();
-        if self.divider != Database::<K, V>::default().divider {
-            contents.push_str(SPLIT_SETTING);
-            contents.push(self.divider);
-            contents.push('\n');
-        }
-        let key_value_pairs = self.generate_key_value_pairs();
-        for (key, value) in key_value_pairs {
-            contents.push_str(&key.to_string(self.divider));
-            contents.push('\t');
-            contents.push_str(&value.to_string());
-            contents.push('\n');
-        }
+        Database::load_encrypted(file, passphrase, &LoadOptions::default())
+            .map(|(database, _)| database)
+    }
+
+    /// Like load for files new_from_encrypted_file reads.
+    #[cfg(feature = "encryption")]
+    pub fn load_encrypted<F>(
+        file: &mut F,
+        passphrase: &str,
+        options: &LoadOptions,
+    ) -> Result<(Database<K, V>, LoadReport)>
+    where
+        F: Read,
+    {
+        let contents = Database::<K, V>::decrypted(file, passphrase)?;
+        Database::read_file(&mut contents.as_slice(), Some(passphrase), options)
+    }
+
+    /// Contents of file, decrypted when the whole file is encrypted.
+    #[cfg(feature = "encryption")]
+    fn decrypted<F>(file: &mut F, passphrase: &str) -> Result<Vec<u8>>
+    where
+        F: Read,
+    {
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        if encryption::is_encrypted(&contents) {
+            contents = encryption::decrypt(&contents, passphrase)?;
+        }
+        Ok(contents)
+    }
+
+    /// Like flush, sealing secret values one by one with a key derived from
+    /// passphrase. The rest of the file stays readable.
+    #[cfg(feature = "encryption")]
+    pub fn flush_sealed<F>(&mut self, file: &mut F, passphrase: &str) -> Result<()>
+    where
+        F: Write,
+    {
+        if self.secrets.is_empty() {
+            return self.flush(file);
+        }
+        let cipher = encryption::ValueCipher::new(passphrase)?;
+        self.write_to(
+            file,
+            Some((cipher.header(), |key: &str, value: &str| {
+                cipher.seal(key, value)
+            })),
+            true,
+        )
+    }
+
+    /// Like flush, encrypting the file with a key derived from passphrase.
+    #[cfg(feature = "encryption")]
+    pub fn flush_encrypted<F>(&mut self, file: &mut F, passphrase: &str) -> Result<()>
+    where
+        F: Write,
+    {
+        // Secret values are as they are inside the encrypted file
+        let mut contents = Vec::new();
+        self.write_to(&mut contents, NO_SEALING, true)?;
+        file.write_all(&encryption::encrypt(&contents, passphrase)?)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Writes the database followed by a checksum line. A database with
+    /// secret values is written with flush_sealed or flush_encrypted instead.
+    pub fn flush<F>(&mut self, file: &mut F) -> Result<()>
+    where
+        F: Write,
+    {
+        if !self.secrets.is_empty() {
+            return Err(DBError::SealingRequired);
+        }
+        self.write_to(file, NO_SEALING, true)
+    }
+
+    /// Like flush without the checksum line, for files meant to be edited by
+    /// hand. Secret values are included as they are.
+    pub fn export<F>(&self, file: &mut F) -> Result<()>
+    where
+        F: Write,
+    {
+        self.write_to(file, NO_SEALING, false)
+    }
+
+    /// Sealing holds the header of the key and the function sealing a secret value.
+    fn write_to<F, S>(
+        &self,
+        file: &mut F,
+        sealing: Option<(String, S)>,
+        with_checksum: bool,
+    ) -> Result<()>
+    where
+        F: Write,
+        S: Fn(&str, &str) -> Result<String>,
+    {
+        let mut contents = String::new();
+        let push_lines = |contents: &mut String, lines: &[String]| {
+            for line in lines {
+                contents.push_str(line);
+                contents.push('\n');
+            }
+        };
+        push_lines(&mut contents, self.layout.header());
+        if with_checksum {
+            contents.push_str(checksum::MARKER);
+            contents.push('\n');
+        }
+        if self.divider != Database::<K, V>::default().divider {
+            contents.push_str(SPLIT_SETTING);
+            contents.push(self.divider);
+            contents.push('\n');
+        }
+        if self.key_order != KeyOrder::default() {
+            contents.push_str(ORDER_SETTING);
+            contents.push_str(&self.key_order.to_string());
+            contents.push('\n');
+        }
+        if !self.collation.is_binary() {
+            contents.push_str(COLLATION_SETTING);
+            contents.push_str(&self.collation.to_string());
+            contents.push('\n');
+        }
+        for secret in &self.secrets {
+            contents.push_str(SECRET_SETTING);
+            contents.push_str(&secret.to_string(self.divider));
+            contents.push('\n');
+        }
+        if let Some((header, _)) = &sealing {
+            contents.push_str(SEALED_SETTING);
+            contents.push_str(header);
+            contents.push('\n');
+        }
+        let key_value_pairs = match self.key_order {
+            KeyOrder::Sorted => self
+                .layout
+                .sort(self.generate_key_value_pairs(), |a, b| self.compare(a, b)),
+            KeyOrder::Insertion => self.generate_key_value_pairs(),
+        };
+        for (key, value) in key_value_pairs {
+            push_lines(&mut contents, self.layout.comments(&key));
+            let written = key.to_string(self.divider);
+            contents.push_str(&written);
+            contents.push('\t');
+            match &sealing {
+                Some((_, seal)) if self.is_secret(&key) => {
+                    contents.push_str(&seal(&written, &value.to_string())?)
+                }
+                // Every further line of the value is indented by a tab
+                _ => contents.push_str(&value.to_string().replace('\n', "\n\t")),
+            }
+            contents.push('\n');
+        }
+        push_lines(&mut contents, self.layout.footer());
+        if with_checksum {
+            checksum::append(&mut contents);
+        }
 
         let mut writer = BufWriter::new(file);
         writer.write_all(contents.as_bytes())?;
@@ -219,51 +1389,81 @@ where
         Ok(())
     }
 
-    #[cfg(any(feature = "cli-features"))]
+    #[cfg(feature = "cli-features")]
     pub fn get_values_cli(&self, key: &Key<K>) -> Result<()> {
-        let mut contents = Vec::new();
-        let key_value_pairs = self.get_values(key);
-        for (key, value) in key_value_pairs {
-            contents.push(vec![
-                key.to_string(self.divider)
-                    .cell()
-                    .align(Align::Center)
-                    .padding(Padding::builder().right(1).build()),
-                value.to_string().cell().align(Align::Center),
-            ])
-        }
-
-        let table = contents.table().title(vec![
-            "Key".cell().justify(Justify::Center).align(Align::Center),
-            "Value".cell().justify(Justify::Center).align(Align::Center),
-        ]);
-        Ok(print_stdout(table)?)
+        self.render_values(key, &OutputFormat::Table, &mut std::io::stdout())
     }
 
-    #[cfg(any(feature = "cli-features"))]
+    #[cfg(feature = "cli-features")]
     pub fn print_cli(&mut self) -> Result<()> {
-        let mut contents = Vec::new();
-        let key_value_pairs = self.generate_key_value_pairs();
-        for (key, value) in key_value_pairs {
-            contents.push(vec![
-                key.to_string(self.divider)
-                    .cell()
-                    .align(Align::Center)
-                    .padding(Padding::builder().right(1).build()),
-                value.to_string().cell().align(Align::Center),
-            ])
-        }
-
-        let table = contents.table().title(vec![
-            "Key".cell().justify(Justify::Center).align(Align::Center),
-            "Value".cell().justify(Justify::Center).align(Align::Center),
-        ]);
-        Ok(print_stdout(table)?)
+        self.render(&OutputFormat::Table, &mut std::io::stdout())
+    }
+
+    /// Renders the values under key with the renderer, into writer.
+    #[cfg(feature = "cli-features")]
+    pub fn render_values<W>(
+        &self,
+        key: &Key<K>,
+        renderer: &dyn Renderer,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        W: Write,
+    {
+        let entries = self.entries(self.get_values(key));
+        renderer.render(&entries, writer)?;
+        Ok(writer.flush()?)
+    }
+
+    /// Renders the whole database with the renderer, into writer.
+    #[cfg(feature = "cli-features")]
+    pub fn render<W>(&self, renderer: &dyn Renderer, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        let entries = self.entries(self.generate_key_value_pairs());
+        renderer.render(&entries, writer)?;
+        Ok(writer.flush()?)
+    }
+
+    #[cfg(feature = "cli-features")]
+    fn entries(&self, key_value_pairs: Vec<(Key<K>, &V)>) -> Vec<Entry> {
+        key_value_pairs
+            .into_iter()
+            .map(|(key, value)| Entry {
+                segments: (0..key.size())
+                    .filter_map(|index| key.get_inner_key(index))
+                    .map(|segment| segment.to_string(self.divider))
+                    .collect(),
+                key: key.to_string(self.divider),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    /// Every key value pair in key order, taking the values.
+    fn into_pairs(mut self) -> Vec<(Key<K>, V)> {
+        let segments: Vec<Key<K>> = self.nodes().into_iter().map(|(s, _)| s.clone()).collect();
+        let mut result = Vec::new();
+        for segment in segments {
+            match self.map.remove(&segment) {
+                Some(NodeType::Parent(parent)) => {
+                    for (temp_key, value) in parent.into_pairs() {
+                        let mut key = Key::new_from_key(&segment);
+                        key.add(&temp_key);
+                        result.push((key, value));
+                    }
+                }
+                Some(NodeType::Value(value)) => result.push((segment, value)),
+                None => (),
+            }
+        }
+        result
     }
 
     pub fn generate_key_value_pairs(&self) -> Vec<(Key<K>, &V)> {
         let mut result = Vec::new();
-        for (single_key, value) in &self.map {
+        for (single_key, value) in self.nodes() {
             let key = Key::new_from_key(single_key);
             match value {
                 NodeType::Parent(parent) => {
@@ -287,6 +1487,7 @@ where
 #[cfg(test)]
 mod tests {
     use crate::database::*;
+    use crate::load::LoadMode;
     use std::io::Cursor;
 
     /// This Creates a test struct that errors when Read::read(ReadError) is called.
@@ -346,7 +1547,7 @@ mod tests {
 
     #[test]
     fn database_new_from_file_read_error() {
-        let mut reader = ReadError::default();
+        let mut reader = ReadError;
 
         let result = Database::<usize, usize>::new_from_file(&mut reader);
         assert!(result.is_err());
@@ -362,7 +1563,420 @@ mod tests {
 
         let result = Database::<usize, usize>::new_from_file(&mut file);
         assert!(result.is_err());
-        assert_eq!(DBError::CorruptDBFile, result.err().unwrap());
+        assert_eq!(
+            DBError::CorruptDBFile { line: Some(1) },
+            result.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn database_checksum() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(Key::new_from_str("db.host", '.')?, "localhost".into())?;
+
+        let mut contents = Vec::new();
+        db.flush(&mut contents)?;
+        let contents = String::from_utf8(contents).unwrap();
+        assert!(contents.starts_with("format:checksum\ndb.host\tlocalhost\nchecksum:"));
+        let loaded = Database::<String, String>::new_from_file(&mut contents.as_bytes())?;
+        assert!(loaded.diff(&db).is_empty());
+
+        // Truncations and edits by hand fail, lenient mode loads them with a warning
+        let lenient = LoadOptions {
+            mode: LoadMode::Lenient,
+        };
+        let changed = contents.replace("localhost", "example.com");
+        assert_eq!(
+            DBError::ChecksumMismatch,
+            Database::<String, String>::new_from_file(&mut changed.as_bytes())
+                .err()
+                .unwrap()
+        );
+        let (loaded, report) = Database::<String, String>::load(&mut changed.as_bytes(), &lenient)?;
+        assert_eq!(
+            Some(&"example.com".to_string()),
+            loaded.get(&Key::new_from_str("db.host", '.')?)
+        );
+        assert_eq!(ProblemKind::ChecksumMismatch, report.warnings[0].kind);
+        assert_eq!(vec![3], report.skipped_lines());
+        let truncated = checksum::body(&contents);
+        assert_eq!(
+            DBError::ChecksumMismatch,
+            Database::<String, String>::new_from_file(&mut truncated.as_bytes())
+                .err()
+                .unwrap()
+        );
+        let added = format!("{}# note\na.d\t3\n", contents);
+        let (loaded, report) = Database::<String, String>::load(&mut added.as_bytes(), &lenient)?;
+        assert_eq!(
+            Some(&"3".to_string()),
+            loaded.get(&Key::new_from_str("a.d", '.')?)
+        );
+        assert_eq!(3, report.warnings[0].line);
+
+        let mut exported = Vec::new();
+        db.export(&mut exported)?;
+        assert_eq!("db.host\tlocalhost\n", String::from_utf8(exported).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn database_fsck() -> Result<()> {
+        let contents = "split:/\n\
+                        \torphan\n\
+                        db/host\tlocalhost\n\
+                        no tab\n\
+                        \tcontinued\n\
+                        db/port\t80\n\
+                        db\t1\n\
+                        split:.\n\
+                        db/port/number\t2\n\
+                        db/port\t8080\n";
+        let fsck = Database::<String, u16>::fsck(&mut contents.as_bytes())?;
+        let kinds: Vec<(usize, ProblemKind)> = fsck
+            .problems
+            .iter()
+            .map(|problem| (problem.line, problem.kind.clone()))
+            .collect();
+        assert_eq!(
+            vec![
+                (2, ProblemKind::OrphanContinuation),
+                (3, ProblemKind::BadValue),
+                (4, ProblemKind::MalformedLine),
+                (6, ProblemKind::DuplicateKey { replaced_on: 10 }),
+                (
+                    7,
+                    ProblemKind::ValueOnParent {
+                        existing: "db/port".into()
+                    }
+                ),
+                (8, ProblemKind::BadSplitHeader),
+                (
+                    9,
+                    ProblemKind::KeyBelowValue {
+                        existing: "db/port".into()
+                    }
+                ),
+            ],
+            kinds
+        );
+        assert_eq!("no tab\n\tcontinued", fsck.problems[2].text);
+        assert_eq!(
+            DBError::ParseError { line: 3 },
+            fsck.problems[1].error(fsck.database.divider())
+        );
+        assert_eq!(
+            DBError::InsertValueToDirectory {
+                key: Key::new_from_str("db", '/')?,
+                existing: Key::new_from_str("db/port", '/')?,
+                divider: '/',
+                line: Some(7),
+            },
+            fsck.problems[4].error(fsck.database.divider())
+        );
+        // Without a key that parses only the line is known
+        let mut keyless = fsck.problems[4].clone();
+        keyless.text = "no tab".into();
+        assert_eq!(DBError::CorruptDBFile { line: Some(7) }, keyless.error('/'));
+        // Everything else loads, the later value wins
+        assert_eq!(1, fsck.database.value_count());
+        assert_eq!(
+            Some(&8080),
+            fsck.database.get(&Key::new_from_str("db/port", '/')?)
+        );
+
+        let mut rejected = Vec::new();
+        fsck.write_rejected(&mut rejected)?;
+        assert!(String::from_utf8(rejected)
+            .unwrap()
+            .starts_with("# line 2: continuation line without a value\n\torphan\n"));
+        // Comments stay with the entry they are before
+        let fsck = Database::<String, u16>::fsck(&mut "# Cache\nno tab\n".as_bytes())?;
+        let mut rejected = Vec::new();
+        fsck.write_rejected(&mut rejected)?;
+        assert_eq!(
+            "# Cache\n# line 2: no tab between key and value\nno tab\n",
+            String::from_utf8(rejected).unwrap()
+        );
+
+        // Loading fails on the first problem
+        assert_eq!(
+            DBError::CorruptDBFile { line: Some(2) },
+            Database::<String, u16>::new_from_file(&mut contents.as_bytes())
+                .err()
+                .unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn database_load_modes() -> Result<()> {
+        let contents = "db.port\t80\nno tab\ndb.port\t8080\n";
+        let load =
+            |mode| Database::<String, u16>::load(&mut contents.as_bytes(), &LoadOptions { mode });
+
+        let (lenient, report) = load(LoadMode::Lenient)?;
+        assert_eq!(
+            Some(&8080),
+            lenient.get(&Key::new_from_str("db.port", '.')?)
+        );
+        let lines: Vec<usize> = report.warnings.iter().map(|warning| warning.line).collect();
+        assert_eq!(vec![1, 2], lines);
+        assert_eq!(vec![2], report.skipped_lines());
+
+        assert_eq!(
+            DBError::CorruptDBFile { line: Some(2) },
+            load(LoadMode::Normal).err().unwrap()
+        );
+        let duplicates = "db.port\t80\ndb.port\t8080\n";
+        let (normal, report) =
+            Database::<String, u16>::load(&mut duplicates.as_bytes(), &LoadOptions::default())?;
+        assert_eq!(Some(&8080), normal.get(&Key::new_from_str("db.port", '.')?));
+        assert_eq!(1, report.warnings.len());
+        // The duplicate fails where it appears again
+        assert_eq!(
+            DBError::CorruptDBFile { line: Some(2) },
+            Database::<String, u16>::load(
+                &mut duplicates.as_bytes(),
+                &LoadOptions {
+                    mode: LoadMode::Strict
+                }
+            )
+            .err()
+            .unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn database_layout() -> Result<()> {
+        let contents = "# Services of the staging cluster\n\
+                        \n\
+                        # Web first\n\
+                        web.port\t80\n\
+                        web.host\texample.com\n\
+                        \n\
+                        # The database\n\
+                        db.host\tlocalhost\n\
+                        # end\n";
+        let mut db = Database::<String, String>::new_from_file(&mut contents.as_bytes())?;
+        let mut exported = Vec::new();
+        db.export(&mut exported)?;
+        assert_eq!(contents, String::from_utf8(exported).unwrap());
+        assert_eq!(
+            ["# Services of the staging cluster".to_string(), "".into()],
+            db.layout().header()
+        );
+
+        // New keys go after the closest key sorting before them
+        db.insert(Key::new_from_str("web.name", '.')?, "web".into())?;
+        db.insert(Key::new_from_str("app.name", '.')?, "app".into())?;
+        db.remove(&Key::new_from_str("db.host", '.')?);
+        assert!(db
+            .layout()
+            .comments(&Key::new_from_str("db.host", '.')?)
+            .is_empty());
+        let mut exported = Vec::new();
+        db.export(&mut exported)?;
+        assert_eq!(
+            "# Services of the staging cluster\n\
+             \n\
+             app.name\tapp\n\
+             # Web first\n\
+             web.port\t80\n\
+             web.host\texample.com\n\
+             web.name\tweb\n\
+             # end\n",
+            String::from_utf8(exported).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn database_key_order() -> Result<()> {
+        let mut db = Database::<String, String>::with_key_order(KeyOrder::Insertion);
+        db.insert(Key::new_from_str("rules.deny", '.')?, "all".into())?;
+        db.insert(Key::new_from_str("middleware.log", '.')?, "on".into())?;
+        db.insert(Key::new_from_str("rules.allow", '.')?, "admin".into())?;
+        db.insert(Key::new_from_str("middleware.auth", '.')?, "on".into())?;
+        db.remove(&Key::new_from_str("middleware.log", '.')?);
+        db.insert(Key::new_from_str("middleware.log", '.')?, "off".into())?;
+
+        let segments: Vec<String> = db
+            .children(&Key::new_from_str("rules", '.')?)
+            .into_iter()
+            .map(|(segment, _)| segment.to_string('.'))
+            .collect();
+        assert_eq!(vec!["deny", "allow"], segments);
+
+        let mut exported = Vec::new();
+        db.export(&mut exported)?;
+        let contents = "order:insertion\n\
+                        rules.deny\tall\n\
+                        rules.allow\tadmin\n\
+                        middleware.auth\ton\n\
+                        middleware.log\toff\n";
+        assert_eq!(contents, String::from_utf8(exported).unwrap());
+
+        // The header brings the order back on load
+        let loaded = Database::<String, String>::new_from_file(&mut contents.as_bytes())?;
+        assert_eq!(KeyOrder::Insertion, loaded.key_order());
+        let keys: Vec<String> = loaded
+            .generate_key_value_pairs()
+            .into_iter()
+            .map(|(key, _)| key.to_string('.'))
+            .collect();
+        assert_eq!(
+            vec![
+                "rules.deny",
+                "rules.allow",
+                "middleware.auth",
+                "middleware.log"
+            ],
+            keys
+        );
+
+        let mut sorted = loaded;
+        sorted.set_key_order(KeyOrder::Sorted);
+        let mut exported = Vec::new();
+        sorted.export(&mut exported)?;
+        assert_eq!(
+            "middleware.auth\ton\n\
+             middleware.log\toff\n\
+             rules.allow\tadmin\n\
+             rules.deny\tall\n",
+            String::from_utf8(exported).unwrap()
+        );
+
+        // A child put into map directly still shows up, after the others
+        let mut db = Database::<String, String>::with_key_order(KeyOrder::Insertion);
+        db.insert(Key::new_from_str("zlib", '.')?, "on".into())?;
+        db.map.insert(
+            Key::new_from_str("auth", '.')?,
+            NodeType::Value("token".into()),
+        );
+        let mut exported = Vec::new();
+        db.export(&mut exported)?;
+        assert_eq!(
+            "order:insertion\nzlib\ton\nauth\ttoken\n",
+            String::from_utf8(exported).unwrap()
+        );
+
+        let fsck = Database::<String, String>::fsck(&mut "order:random\na\t1\n".as_bytes())?;
+        assert_eq!(ProblemKind::BadOrderHeader, fsck.problems[0].kind);
+        Ok(())
+    }
+
+    #[test]
+    fn database_collation() -> Result<()> {
+        let key = |key: &str| Key::<String>::new_from_str(key, '.');
+        let mut db = Database::<String, String>::new();
+        db.insert(key("node.10")?, "ten".into())?;
+        db.insert(key("node.2")?, "two".into())?;
+        db.insert(key("Prod.host")?, "a".into())?;
+        db.insert(key("prod.host")?, "b".into())?;
+        db.mark_secret(key("PROD")?);
+
+        // Prod and prod would collide once case folds
+        let folding = Collation::from_str("natural,case-folding").unwrap();
+        assert_eq!(
+            DBError::CollationConflict {
+                keys: vec![key("Prod.host")?, key("prod.host")?],
+                divider: '.',
+            },
+            db.set_collation(folding).err().unwrap()
+        );
+        assert_eq!(Collation::default(), db.collation());
+        assert_eq!(Some(&"b".to_string()), db.get(&key("prod.host")?));
+
+        db.remove(&key("prod.host")?);
+        db.set_collation(folding)?;
+        assert_eq!(Some(&"a".to_string()), db.get(&key("PROD.Host")?));
+        assert!(db.is_secret(&key("prod.host")?));
+        assert_eq!(
+            Ordering::Less,
+            db.compare(&key("node.2")?, &key("node.10")?)
+        );
+        let mut exported = Vec::new();
+        db.export(&mut exported)?;
+        let contents = "collation:natural,case-folding\n\
+                        secret:Prod\n\
+                        node.2\ttwo\n\
+                        node.10\tten\n\
+                        Prod.host\ta\n";
+        assert_eq!(contents, String::from_utf8(exported).unwrap());
+
+        let mut loaded = Database::<String, String>::new_from_file(&mut contents.as_bytes())?;
+        assert_eq!(db.collation(), loaded.collation());
+        loaded.insert(key("NODE.2")?, "again".into())?;
+        assert_eq!(Some(&"again".to_string()), loaded.get(&key("node.2")?));
+        assert_eq!(
+            "node.2",
+            loaded.keys(&key("node")?)[0].to_string(loaded.divider())
+        );
+        // Leading zeros only order naturally, 02 is a key of its own
+        loaded.insert(key("NODE.02")?, "zero".into())?;
+        assert_eq!(Some(&"again".to_string()), loaded.get(&key("node.2")?));
+        assert_eq!(Some(&"zero".to_string()), loaded.get(&key("node.02")?));
+
+        // Case insensitive keys are stored in lower case
+        loaded.set_collation(Collation::from_str("case-insensitive").unwrap())?;
+        let keys: Vec<String> = loaded
+            .generate_key_value_pairs()
+            .into_iter()
+            .map(|(key, _)| key.to_string('.'))
+            .collect();
+        assert_eq!(vec!["node.02", "node.10", "node.2", "prod.host"], keys);
+
+        // Collisions below a value are found however the keys sort
+        let mut db = Database::<String, String>::new();
+        db.insert(key("Prod")?, "1".into())?;
+        db.insert(key("prod.a")?, "2".into())?;
+        db.insert(key("PROD.b")?, "3".into())?;
+        assert_eq!(
+            DBError::CollationConflict {
+                keys: vec![key("Prod")?, key("PROD.b")?, key("Prod")?, key("prod.a")?],
+                divider: '.',
+            },
+            db.set_collation(folding).err().unwrap()
+        );
+
+        let fsck = Database::<String, String>::fsck(&mut "collation:random\na\t1\n".as_bytes())?;
+        assert_eq!(ProblemKind::BadCollationHeader, fsck.problems[0].kind);
+        Ok(())
+    }
+
+    #[test]
+    fn database_multi_line_values() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(
+            Key::new_from_str("cert", '.')?,
+            "-----BEGIN-----\n\tindented\n\n-----END-----\n".into(),
+        )?;
+        db.insert(Key::new_from_str("name", '.')?, "single".into())?;
+
+        let mut contents = Vec::new();
+        db.export(&mut contents)?;
+        assert_eq!(
+            "cert\t-----BEGIN-----\n\t\tindented\n\t\n\t-----END-----\n\t\nname\tsingle\n",
+            String::from_utf8(contents.clone()).unwrap()
+        );
+
+        let loaded = Database::<String, String>::new_from_file(&mut Cursor::new(contents))?;
+        assert_eq!(
+            db.get(&Key::new_from_str("cert", '.')?),
+            loaded.get(&Key::new_from_str("cert", '.')?)
+        );
+        assert_eq!(2, loaded.value_count());
+
+        // A continuation needs a value to continue
+        let result = Database::<String, String>::new_from_file(&mut Cursor::new("\tvalue"));
+        assert_eq!(
+            DBError::CorruptDBFile { line: Some(1) },
+            result.err().unwrap()
+        );
+
+        Ok(())
     }
 
     #[test]
@@ -421,7 +2035,15 @@ mod tests {
         let root_result = db.insert(root, "root".into());
 
         assert!(root_result.is_err());
-        assert_eq!(DBError::InsertValueToDirectory, root_result.err().unwrap());
+        assert_eq!(
+            DBError::InsertValueToDirectory {
+                key: Key::new_from_str("1.2.3.4", '.')?,
+                existing: Key::new_from_str("1.2.3.4.5", '.')?,
+                divider: '.',
+                line: None
+            },
+            root_result.err().unwrap()
+        );
 
         Ok(())
     }
@@ -440,8 +2062,554 @@ mod tests {
         let key_result = db.insert(key, "root".into());
 
         assert!(key_result.is_err());
-        assert_eq!(DBError::MultiKeyExtendValueKey, key_result.err().unwrap());
+        assert_eq!(
+            DBError::MultiKeyExtendValueKey {
+                key: Key::new_from_str("1.2.3.4.5", '.')?,
+                existing: Key::new_from_str("1.2.3.4", '.')?,
+                divider: '.',
+                line: None
+            },
+            key_result.err().unwrap()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_insert_if_absent() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        let key = Key::new_from_str("deploy.lock", '.')?;
+
+        assert_eq!((), db.insert_if_absent(key.clone(), "job-1".into())?);
+        assert_eq!(
+            DBError::ConditionFailed,
+            db.insert_if_absent(key.clone(), "job-2".into())
+                .err()
+                .unwrap()
+        );
+        assert_eq!("job-1", db.get(&key).unwrap());
+
+        // A key below a value is a conflict, not a failed condition
+        let below = Key::new_from_str("deploy.lock.owner", '.')?;
+        assert!(matches!(
+            db.insert_if_absent(below, "job-2".into()),
+            Err(DBError::MultiKeyExtendValueKey { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_update_if_present() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        let key = Key::new_from_str("deploy.lock", '.')?;
+
+        assert_eq!(
+            DBError::ConditionFailed,
+            db.update_if_present(key.clone(), "job-1".into())
+                .err()
+                .unwrap()
+        );
+        assert!(db.get(&key).is_none());
+
+        db.insert(key.clone(), "free".into())?;
+        db.update_if_present(key.clone(), "job-1".into())?;
+        assert_eq!("job-1", db.get(&key).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_compare_and_swap() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        let key = Key::new_from_str("deploy.lock", '.')?;
+
+        db.compare_and_swap(&key, None, "free".into())?;
+        db.compare_and_swap(&key, Some(&"free".into()), "job-1".into())?;
+
+        let result = db.compare_and_swap(&key, Some(&"free".into()), "job-2".into());
+        assert_eq!(DBError::ConditionFailed, result.err().unwrap());
+        let result = db.compare_and_swap(&key, None, "job-2".into());
+        assert_eq!(DBError::ConditionFailed, result.err().unwrap());
+        assert_eq!("job-1", db.get(&key).unwrap());
+
+        // The value of a parent does not match
+        let below = Key::new_from_str("deploy.lock.owner", '.')?;
+        let result = db.compare_and_swap(&below, Some(&"job-1".into()), "job-2".into());
+        assert_eq!(DBError::ConditionFailed, result.err().unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_children_and_keys() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(Key::new_from_str("services.web.port", '.')?, "80".into())?;
+        db.insert(Key::new_from_str("services.web.host", '.')?, "a".into())?;
+        db.insert(Key::new_from_str("services.db", '.')?, "b".into())?;
+        let services = Key::new_from_str("services", '.')?;
+
+        let children = db.children(&services);
+        assert_eq!(2, children.len());
+        assert_eq!(Key::new_from_str("db", '.')?, children[0].0);
+        assert!(children[0].1.is_value());
+        assert_eq!(Key::new_from_str("web", '.')?, children[1].0);
+        assert_eq!(2, children[1].1.get_parent_ref().unwrap().value_count());
+
+        assert_eq!(
+            vec![Key::new_from_str("services.db", '.')?],
+            db.keys(&services)
+        );
+        assert!(db
+            .children(&Key::new_from_str("services.db", '.')?)
+            .is_empty());
+        assert!(db.children(&Key::new_from_str("missing", '.')?).is_empty());
+        assert_eq!(3, db.value_count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_search() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(
+            Key::new_from_str("web.host", '.')?,
+            "old.example.com".into(),
+        )?;
+        db.insert(Key::new_from_str("db.host", '.')?, "new.example.com".into())?;
+        db.insert(Key::new_from_str("old.flag", '.')?, "true".into())?;
+
+        let values = db.search("old", SearchScope::Values);
+        assert_eq!(1, values.len());
+        assert_eq!(Key::new_from_str("web.host", '.')?, values[0].0);
+
+        let keys = db.search("old", SearchScope::Keys);
+        assert_eq!(1, keys.len());
+        assert_eq!(Key::new_from_str("old.flag", '.')?, keys[0].0);
+
+        assert_eq!(2, db.search("old", SearchScope::Both).len());
+        assert!(db.search("missing", SearchScope::Both).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_prune() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(Key::new_from_str("a.b.c", '.')?, "1".into())?;
+        db.insert(Key::new_from_str("a.d", '.')?, "2".into())?;
+
+        db.remove(&Key::new_from_str("a.b.c", '.')?);
+        assert!(db.get_node(&Key::new_from_str("a.b", '.')?).is_some());
+        db.prune();
+        assert!(db.get_node(&Key::new_from_str("a.b", '.')?).is_none());
+        assert_eq!(
+            Some(&"2".to_string()),
+            db.get(&Key::new_from_str("a.d", '.')?)
+        );
+
+        db.remove(&Key::new_from_str("a.d", '.')?);
+        db.prune();
+        assert!(db.is_empty());
+
+        // Only the parents of the key
+        db.insert(Key::new_from_str("a.b.c", '.')?, "1".into())?;
+        db.insert(Key::new_from_str("e.f", '.')?, "3".into())?;
+        db.remove(&Key::new_from_str("e.f", '.')?);
+        db.remove(&Key::new_from_str("a.b.c", '.')?);
+        db.prune_parents(&Key::new_from_str("a.b.c", '.')?);
+        assert!(db.get_node(&Key::new_from_str("a", '.')?).is_none());
+        assert!(db.get_node(&Key::new_from_str("e", '.')?).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_diff() -> Result<()> {
+        let key = |key: &str| Key::<String>::new_from_str(key, '.').unwrap();
+        let mut staging = Database::<String, String>::new();
+        let mut prod = Database::<String, String>::new();
+        for (name, value) in [("same", "1"), ("host", "a"), ("gone", "x"), ("flag", "on")] {
+            staging.insert(key(name), value.into())?;
+        }
+        staging.insert(key("tls.cert"), "c".into())?;
+        for (name, value) in [
+            ("same", "1"),
+            ("host", "b"),
+            ("new.deep", "y"),
+            ("tls", "off"),
+        ] {
+            prod.insert(key(name), value.into())?;
+        }
+        prod.insert(key("flag.mode"), "m".into())?;
+
+        assert_eq!(
+            vec![
+                Change::TypeChanged {
+                    key: key("flag"),
+                    before: Some("on".into()),
+                    after: None
+                },
+                Change::Added {
+                    key: key("flag.mode"),
+                    value: "m".into()
+                },
+                Change::Removed {
+                    key: key("gone"),
+                    value: "x".into()
+                },
+                Change::Changed {
+                    key: key("host"),
+                    before: "a".into(),
+                    after: "b".into()
+                },
+                Change::Added {
+                    key: key("new.deep"),
+                    value: "y".into()
+                },
+                Change::TypeChanged {
+                    key: key("tls"),
+                    before: None,
+                    after: Some("off".into())
+                },
+                Change::Removed {
+                    key: key("tls.cert"),
+                    value: "c".into()
+                },
+            ],
+            staging.diff(&prod)
+        );
+        assert!(prod.diff(&prod).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_diff_and_merge_fold_case() -> Result<()> {
+        let key = |key: &str| Key::<String>::new_from_str(key, '.').unwrap();
+        let database = |values: &[(&str, &str)]| {
+            let mut database = Database::<String, String>::new();
+            database
+                .set_collation(Collation::from_str("case-folding").unwrap())
+                .unwrap();
+            for (name, value) in values {
+                database.insert(key(name), value.to_string()).unwrap();
+            }
+            database
+        };
+        let base = database(&[("Host.name", "a"), ("port", "1")]);
+        let ours = database(&[("host.name", "a"), ("port", "2")]);
+        let theirs = database(&[("HOST.Name", "b"), ("port", "1")]);
+
+        assert_eq!(1, base.diff(&ours).len());
+        assert_eq!(
+            vec![Change::Changed {
+                key: key("Host.name"),
+                before: "a".into(),
+                after: "b".into()
+            }],
+            base.diff(&theirs)
+        );
+
+        let merged = Database::merge(&base, &ours, &theirs, MergeStrategy::Fail)?;
+        assert!(merged.is_clean());
+        assert_eq!(
+            Some(&"b".to_string()),
+            merged.database.get(&key("host.name"))
+        );
+        assert_eq!(Some(&"2".to_string()), merged.database.get(&key("port")));
+        assert_eq!(2, merged.database.generate_key_value_pairs().len());
+        Ok(())
+    }
+
+    #[test]
+    fn database_merge() -> Result<()> {
+        let key = |key: &str| Key::<String>::new_from_str(key, '.').unwrap();
+        let database = |values: &[(&str, &str)]| {
+            let mut database = Database::<String, String>::new();
+            for (name, value) in values {
+                database.insert(key(name), value.to_string()).unwrap();
+            }
+            database
+        };
+        let base = database(&[("same", "1"), ("ours", "1"), ("theirs", "1"), ("both", "1")]);
+        let ours = database(&[
+            ("same", "1"),
+            ("ours", "2"),
+            ("theirs", "1"),
+            ("both", "2"),
+            ("tls", "off"),
+        ]);
+        let theirs = database(&[
+            ("same", "1"),
+            ("ours", "1"),
+            ("theirs", "3"),
+            ("both", "3"),
+            ("tls.cert", "c"),
+            ("new", "n"),
+        ]);
+
+        let merged = Database::merge(&base, &ours, &theirs, MergeStrategy::Ours)?;
+        assert_eq!(
+            vec![
+                Conflict::Value {
+                    key: key("both"),
+                    base: Some("1".into()),
+                    ours: Some("2".into()),
+                    theirs: Some("3".into())
+                },
+                Conflict::Structure { key: key("tls") },
+            ],
+            merged.conflicts
+        );
+        assert_eq!(Some(&"2".to_string()), merged.database.get(&key("both")));
+        assert_eq!(Some(&"2".to_string()), merged.database.get(&key("ours")));
+        assert_eq!(Some(&"3".to_string()), merged.database.get(&key("theirs")));
+        assert_eq!(Some(&"n".to_string()), merged.database.get(&key("new")));
+        assert_eq!(Some(&"off".to_string()), merged.database.get(&key("tls")));
+
+        let merged = Database::merge(&base, &ours, &theirs, MergeStrategy::Theirs)?;
+        assert_eq!(Some(&"3".to_string()), merged.database.get(&key("both")));
+        assert_eq!(
+            Some(&"c".to_string()),
+            merged.database.get(&key("tls.cert"))
+        );
+
+        let merged = Database::merge(&base, &ours, &theirs, MergeStrategy::Fail)?;
+        assert!(!merged.is_clean());
+        assert_eq!(Some(&"1".to_string()), merged.database.get(&key("both")));
+        assert!(merged.database.get_node(&key("tls")).is_none());
+
+        let merged = Database::merge(&base, &ours, &ours, MergeStrategy::Fail)?;
+        assert!(merged.is_clean());
+        assert!(merged.database.diff(&ours).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_apply_patch() -> Result<()> {
+        let key = |key: &str| Key::<String>::new_from_str(key, '.').unwrap();
+        let mut staging = Database::<String, String>::new();
+        let mut prod = Database::<String, String>::new();
+        for (name, value) in [
+            ("host", "a"),
+            ("name", "Example"),
+            ("tls", "off"),
+            ("debug", "true"),
+        ] {
+            staging.insert(key(name), value.into())?;
+        }
+        for (name, value) in [("host", "b"), ("title", "Example"), ("tls.cert", "c")] {
+            prod.insert(key(name), value.into())?;
+        }
+
+        let patch = Patch::from_changes(staging.diff(&prod));
+        let mut patched = staging.clone();
+        assert!(patched.check_patch(&patch).is_empty());
+        patched.apply_patch(&patch)?;
+        assert!(patched.diff(&prod).is_empty());
+
+        // Applied twice the expected values no longer match
+        assert_eq!(patch.operations.len(), patched.check_patch(&patch).len());
+        assert_eq!(
+            DBError::ConditionFailed,
+            patched.apply_patch(&patch).err().unwrap()
+        );
+        assert!(patched.diff(&prod).is_empty());
+
+        // A conflict half way leaves the database as it was
+        let conflicting = Patch {
+            operations: vec![
+                Operation::Remove {
+                    key: key("host"),
+                    old: "a".into(),
+                },
+                Operation::Set {
+                    key: key("name.first"),
+                    old: None,
+                    value: "x".into(),
+                },
+            ],
+        };
+        let mut unchanged = staging.clone();
+        assert_eq!(
+            DBError::MultiKeyExtendValueKey {
+                key: Key::new_from_str("name.first", '.')?,
+                existing: Key::new_from_str("name", '.')?,
+                divider: '.',
+                line: None
+            },
+            unchanged.apply_patch(&conflicting).err().unwrap()
+        );
+        assert!(unchanged.diff(&staging).is_empty());
 
+        // Empty parents the patch does not touch are kept
+        let mut emptied = staging.clone();
+        emptied.insert(key("old.host"), "x".into())?;
+        emptied.remove(&key("old.host"));
+        emptied.apply_patch(&Patch {
+            operations: vec![Operation::Remove {
+                key: key("tls"),
+                old: "off".into(),
+            }],
+        })?;
+        assert!(emptied.get_node(&key("old")).is_some());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn database_encrypted_file() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(Key::new_from_str("db.password", '.')?, "hunter2".into())?;
+
+        let mut contents = Vec::new();
+        db.flush_encrypted(&mut contents, "passphrase")?;
+        assert_eq!(
+            DBError::PassphraseRequired,
+            Database::<String, String>::new_from_file(&mut contents.as_slice())
+                .err()
+                .unwrap()
+        );
+        let loaded = Database::<String, String>::new_from_encrypted_file(
+            &mut contents.as_slice(),
+            "passphrase",
+        )?;
+        assert!(loaded.diff(&db).is_empty());
+
+        // Plain files still load, so they can be encrypted on the next flush
+        let mut plain = Vec::new();
+        db.flush(&mut plain)?;
+        let loaded = Database::<String, String>::new_from_encrypted_file(
+            &mut plain.as_slice(),
+            "passphrase",
+        )?;
+        assert!(loaded.diff(&db).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn database_secrets() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(Key::new_from_str("db.host", '.')?, "localhost".into())?;
+        db.insert(Key::new_from_str("db.auth.user", '.')?, "admin".into())?;
+        db.insert(
+            Key::new_from_str("db.auth.password", '.')?,
+            "hunter2".into(),
+        )?;
+        db.mark_secret(Key::new_from_str("db.auth", '.')?);
+
+        assert!(db.is_secret(&Key::new_from_str("db.auth.password", '.')?));
+        assert!(!db.is_secret(&Key::new_from_str("db.host", '.')?));
+        assert!(!db.is_secret(&Key::new_from_str("db", '.')?));
+
+        let masked = db.masked(&"***".to_string());
+        let password = Key::new_from_str("db.auth.password", '.')?;
+        assert_eq!(Some(&"***".to_string()), masked.get(&password));
+        assert_eq!(
+            Some(&"localhost".to_string()),
+            masked.get(&Key::new_from_str("db.host", '.')?)
+        );
+
+        let mut contents = Vec::new();
+        db.export(&mut contents)?;
+        assert_eq!(
+            "secret:db.auth\ndb.auth.password\thunter2\ndb.auth.user\tadmin\ndb.host\tlocalhost\n",
+            String::from_utf8(contents.clone()).unwrap()
+        );
+        let loaded = Database::<String, String>::new_from_file(&mut contents.as_slice())?;
+        assert!(loaded.is_secret(&password));
+
+        assert!(db.unmark_secret(&Key::new_from_str("db.auth", '.')?));
+        assert!(!db.is_secret(&password));
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn database_sealed_secrets() -> Result<()> {
+        let mut db = Database::<String, String>::new();
+        db.insert(Key::new_from_str("db.host", '.')?, "localhost".into())?;
+        db.insert(Key::new_from_str("db.password", '.')?, "hunter2".into())?;
+        db.mark_secret(Key::new_from_str("db.password", '.')?);
+
+        let mut contents = Vec::new();
+        db.flush_sealed(&mut contents, "passphrase")?;
+        let text = String::from_utf8(contents.clone()).unwrap();
+        assert!(text.starts_with("format:checksum\nsecret:db.password\nsealed:"));
+        assert!(text.contains("\ndb.host\tlocalhost\ndb.password\t"));
+        assert!(!text.contains("hunter2"));
+
+        assert_eq!(
+            DBError::PassphraseRequired,
+            Database::<String, String>::new_from_file(&mut contents.as_slice())
+                .err()
+                .unwrap()
+        );
+        assert_eq!(
+            DBError::DecryptionFailed,
+            Database::<String, String>::new_from_encrypted_file(&mut contents.as_slice(), "wrong")
+                .err()
+                .unwrap()
+        );
+        let loaded = Database::<String, String>::new_from_encrypted_file(
+            &mut contents.as_slice(),
+            "passphrase",
+        )?;
+        assert!(loaded.diff(&db).is_empty());
+        assert!(loaded.is_secret(&Key::new_from_str("db.password", '.')?));
+
+        // Unsealed secrets are not written
+        assert_eq!(
+            DBError::SealingRequired,
+            db.flush(&mut Vec::new()).err().unwrap()
+        );
+
+        // A sealed value swapped to another key does not open
+        db.insert(Key::new_from_str("dev.password", '.')?, "letmein".into())?;
+        db.mark_secret(Key::new_from_str("dev.password", '.')?);
+        let mut contents = Vec::new();
+        db.flush_sealed(&mut contents, "passphrase")?;
+        let text = String::from_utf8(contents).unwrap();
+        let text = checksum::body(&text);
+        let sealed = |key: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(key))
+                .unwrap()
+                .to_string()
+        };
+        let (dev, prod) = (sealed("dev.password\t"), sealed("db.password\t"));
+        // The checksum is no secret, it is written again
+        let mut swapped = text
+            .replace(&dev, "DEV")
+            .replace(&prod, &dev)
+            .replace("DEV", &prod);
+        checksum::append(&mut swapped);
+        assert_eq!(
+            DBError::DecryptionFailed,
+            Database::<String, String>::new_from_encrypted_file(
+                &mut swapped.as_bytes(),
+                "passphrase"
+            )
+            .err()
+            .unwrap()
+        );
+
+        // A sealed value that is no hex fails on its line
+        let mut broken = text.replace(&prod, "zz");
+        checksum::append(&mut broken);
+        assert_eq!(
+            DBError::ParseError { line: 6 },
+            Database::<String, String>::new_from_encrypted_file(
+                &mut broken.as_bytes(),
+                "passphrase"
+            )
+            .err()
+            .unwrap()
+        );
         Ok(())
     }
 }
@@ -0,0 +1,65 @@
+use crate::key::Key;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// One difference found by Database::diff, going from self to other.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    /// The key only holds a value in other.
+    Added { key: Key<K>, value: V },
+    /// The key only holds a value in self.
+    Removed { key: Key<K>, value: V },
+    /// The key holds a different value on each side.
+    Changed { key: Key<K>, before: V, after: V },
+    /// The key is a value on one side and a parent on the other, the value
+    /// side is Some. The values below the parent follow as Added or Removed.
+    TypeChanged {
+        key: Key<K>,
+        before: Option<V>,
+        after: Option<V>,
+    },
+}
+
+impl<K, V> Change<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    pub fn key(&self) -> &Key<K> {
+        match self {
+            Change::Added { key, .. }
+            | Change::Removed { key, .. }
+            | Change::Changed { key, .. }
+            | Change::TypeChanged { key, .. } => key,
+        }
+    }
+
+    /// The change with every value replaced by mask, for output.
+    pub fn masked(self, mask: &V) -> Change<K, V>
+    where
+        V: Clone,
+    {
+        match self {
+            Change::Added { key, .. } => Change::Added {
+                key,
+                value: mask.clone(),
+            },
+            Change::Removed { key, .. } => Change::Removed {
+                key,
+                value: mask.clone(),
+            },
+            Change::Changed { key, .. } => Change::Changed {
+                key,
+                before: mask.clone(),
+                after: mask.clone(),
+            },
+            Change::TypeChanged { key, before, after } => Change::TypeChanged {
+                key,
+                before: before.map(|_| mask.clone()),
+                after: after.map(|_| mask.clone()),
+            },
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use crate::error::DBError;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::convert::TryInto;
+
+type Result<T> = std::result::Result<T, DBError>;
+
+/// First bytes of an encrypted database file.
+pub const ENCRYPTED_HEADER: &[u8] = b"KVDBENC1";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+// Header, the three argon2 parameters, salt and nonce
+const PREFIX_LENGTH: usize = ENCRYPTED_HEADER.len() + 12 + SALT_LENGTH + NONCE_LENGTH;
+// Largest argon2 parameters a file may ask for, they are read before the
+// file is authenticated: 256 MiB of memory, 10 passes and 8 lanes
+const MAX_M_COST: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 10;
+const MAX_P_COST: u32 = 8;
+
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(ENCRYPTED_HEADER)
+}
+
+/// Encrypts contents with XChaCha20-Poly1305, under a key derived from the
+/// passphrase with Argon2id. The header, argon2 parameters, salt and nonce
+/// are stored in front and are authenticated along with the contents.
+pub fn encrypt(contents: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let params = Params::default();
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+
+    let mut encrypted = ENCRYPTED_HEADER.to_vec();
+    for parameter in [params.m_cost(), params.t_cost(), params.p_cost()] {
+        encrypted.extend_from_slice(&parameter.to_le_bytes());
+    }
+    encrypted.extend_from_slice(&salt);
+
+    let cipher = cipher(passphrase, &salt, params)?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    encrypted.extend_from_slice(&nonce);
+    let sealed = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: contents,
+                aad: &encrypted,
+            },
+        )
+        .map_err(|_| DBError::DecryptionFailed)?;
+    encrypted.extend_from_slice(&sealed);
+    Ok(encrypted)
+}
+
+/// Reverses encrypt, fails when the passphrase is wrong or a byte changed.
+pub fn decrypt(encrypted: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if !is_encrypted(encrypted) || encrypted.len() < PREFIX_LENGTH {
+        return Err(DBError::CorruptDBFile { line: None });
+    }
+    let (prefix, sealed) = encrypted.split_at(PREFIX_LENGTH);
+    let params = read_params(&prefix[ENCRYPTED_HEADER.len()..])?;
+    let salt_start = ENCRYPTED_HEADER.len() + 12;
+    let salt = &prefix[salt_start..salt_start + SALT_LENGTH];
+    let nonce = XNonce::from_slice(&prefix[salt_start + SALT_LENGTH..]);
+
+    cipher(passphrase, salt, params)?
+        .decrypt(
+            nonce,
+            Payload {
+                msg: sealed,
+                aad: prefix,
+            },
+        )
+        .map_err(|_| DBError::DecryptionFailed)
+}
+
+/// Seals single values inside an otherwise readable file. The key is derived
+/// once per file, its argon2 parameters and salt make up the header.
+pub struct ValueCipher {
+    cipher: XChaCha20Poly1305,
+    header: Vec<u8>,
+}
+
+impl ValueCipher {
+    /// A cipher with a fresh salt, for writing a file.
+    pub fn new(passphrase: &str) -> Result<ValueCipher> {
+        let params = Params::default();
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+
+        let mut header = Vec::new();
+        for parameter in [params.m_cost(), params.t_cost(), params.p_cost()] {
+            header.extend_from_slice(&parameter.to_le_bytes());
+        }
+        header.extend_from_slice(&salt);
+        Ok(ValueCipher {
+            cipher: cipher(passphrase, &salt, params)?,
+            header,
+        })
+    }
+
+    /// The cipher a file was written with, from the header it holds.
+    pub fn from_header(passphrase: &str, header: &str) -> Result<ValueCipher> {
+        let header = hex::decode(header).map_err(|_| DBError::CorruptDBFile { line: None })?;
+        if header.len() != 12 + SALT_LENGTH {
+            return Err(DBError::CorruptDBFile { line: None });
+        }
+        let params = read_params(&header)?;
+        Ok(ValueCipher {
+            cipher: cipher(passphrase, &header[12..], params)?,
+            header,
+        })
+    }
+
+    pub fn header(&self) -> String {
+        hex::encode(&self.header)
+    }
+
+    /// Nonce and sealed value as hex, which fits on one line.
+    /// The value only opens again for the same key, as written in the file.
+    pub fn seal(&self, key: &str, value: &str) -> Result<String> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: &self.associated_data(key),
+                },
+            )
+            .map_err(|_| DBError::DecryptionFailed)?;
+        let mut contents = nonce.to_vec();
+        contents.extend_from_slice(&sealed);
+        Ok(hex::encode(&contents))
+    }
+
+    pub fn open(&self, key: &str, sealed: &str) -> Result<String> {
+        let contents = hex::decode(sealed).map_err(|_| DBError::CorruptDBFile { line: None })?;
+        if contents.len() < NONCE_LENGTH {
+            return Err(DBError::CorruptDBFile { line: None });
+        }
+        let (nonce, sealed) = contents.split_at(NONCE_LENGTH);
+        let value = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: &self.associated_data(key),
+                },
+            )
+            .map_err(|_| DBError::DecryptionFailed)?;
+        String::from_utf8(value).map_err(|_| DBError::CorruptDBFile { line: None })
+    }
+
+    /// The header and the key, so a sealed value can not be moved to another key.
+    fn associated_data(&self, key: &str) -> Vec<u8> {
+        let mut data = self.header.clone();
+        data.extend_from_slice(key.as_bytes());
+        data
+    }
+}
+
+/// The argon2 parameters at the start of bytes, refusing any above the
+/// maxima so a crafted file can not make deriving the key too expensive.
+fn read_params(bytes: &[u8]) -> Result<Params> {
+    let parameter =
+        |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+    let (m_cost, t_cost, p_cost) = (parameter(0), parameter(1), parameter(2));
+    if m_cost > MAX_M_COST || t_cost > MAX_T_COST || p_cost > MAX_P_COST {
+        return Err(DBError::CorruptDBFile { line: None });
+    }
+    Params::new(m_cost, t_cost, p_cost, None).map_err(|_| DBError::CorruptDBFile { line: None })
+}
+
+fn cipher(passphrase: &str, salt: &[u8], params: Params) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| DBError::CorruptDBFile { line: None })?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encryption::*;
+
+    #[test]
+    fn encrypt_decrypt() {
+        let encrypted = encrypt(b"key\tsecret\n", "passphrase").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.windows(6).any(|window| window == b"secret"));
+        assert_eq!(
+            b"key\tsecret\n".to_vec(),
+            decrypt(&encrypted, "passphrase").unwrap()
+        );
+
+        assert_eq!(
+            DBError::DecryptionFailed,
+            decrypt(&encrypted, "wrong").err().unwrap()
+        );
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert_eq!(
+            DBError::DecryptionFailed,
+            decrypt(&tampered, "passphrase").err().unwrap()
+        );
+        assert_eq!(
+            DBError::CorruptDBFile { line: None },
+            decrypt(&encrypted[..20], "passphrase").err().unwrap()
+        );
+
+        // Costs beyond the maxima are refused before deriving the key
+        let mut expensive = encrypted.clone();
+        let m_cost = ENCRYPTED_HEADER.len();
+        expensive[m_cost..m_cost + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            DBError::CorruptDBFile { line: None },
+            decrypt(&expensive, "passphrase").err().unwrap()
+        );
+    }
+
+    #[test]
+    fn seal_open_values() {
+        let cipher = ValueCipher::new("passphrase").unwrap();
+        let sealed = cipher.seal("db.password", "hunter2").unwrap();
+        assert!(sealed.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(sealed, cipher.seal("db.password", "hunter2").unwrap());
+
+        let reopened = ValueCipher::from_header("passphrase", &cipher.header()).unwrap();
+        assert_eq!("hunter2", reopened.open("db.password", &sealed).unwrap());
+        let wrong = ValueCipher::from_header("wrong", &cipher.header()).unwrap();
+        assert_eq!(
+            DBError::DecryptionFailed,
+            wrong.open("db.password", &sealed).err().unwrap()
+        );
+        // A value moved to another key does not open
+        assert_eq!(
+            DBError::DecryptionFailed,
+            reopened.open("dev.db.password", &sealed).err().unwrap()
+        );
+        assert_eq!(
+            DBError::CorruptDBFile { line: None },
+            cipher.open("db.password", "not hex").err().unwrap()
+        );
+    }
+}
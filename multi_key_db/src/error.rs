@@ -1,3 +1,4 @@
+use crate::key::Key;
 use std::fmt;
 use std::io::Error;
 
@@ -5,22 +6,128 @@ use std::io::Error;
 pub enum DBError {
     IOError(Error),
     KeyError(KeyError),
-    CorruptDBFile,
-    InsertValueToDirectory,
-    MultiKeyExtendValueKey,
+    /// Line of the file, when the error was found loading one.
+    CorruptDBFile {
+        line: Option<usize>,
+    },
+    /// A key or value of the file on line does not parse, see KeyError::ParseError.
+    ParseError {
+        line: usize,
+    },
+    /// Key holds keys below it, existing is the first of them.
+    /// Both are shown with the divider of the database.
+    InsertValueToDirectory {
+        key: Key<String>,
+        existing: Key<String>,
+        divider: char,
+        line: Option<usize>,
+    },
+    /// A parent of key holds a value, existing is that parent.
+    MultiKeyExtendValueKey {
+        key: Key<String>,
+        existing: Key<String>,
+        divider: char,
+        line: Option<usize>,
+    },
+    /// Keys that would become the same under a new collation, in pairs.
+    /// Shown with the divider of the database.
+    CollationConflict {
+        keys: Vec<Key<String>>,
+        divider: char,
+    },
+    ConditionFailed,
+    KeyNotFound,
+    MergeConflict,
+    PassphraseRequired,
+    DecryptionFailed,
+    ChecksumMismatch,
+    SignatureInvalid,
+    /// A signed file would be written without a new signature.
+    SigningKeyRequired,
+    /// Secret values would be written without sealing them.
+    SealingRequired,
+    /// The file would be written without the lines its load skipped.
+    SkippedLines {
+        lines: Vec<usize>,
+    },
 }
 
 impl fmt::Display for DBError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &*self {
-            DBError::CorruptDBFile => writeln!(f, "Database is corrupted."),
-            DBError::InsertValueToDirectory => {
-                writeln!(f, "Trying to insert value, into a Key that's a directory")
+        match self {
+            DBError::CorruptDBFile { line } => {
+                writeln!(f, "Database is corrupted{}.", on_line(line))
+            }
+            DBError::ParseError { line } => writeln!(f, "Could not parse input on line {}.", line),
+            DBError::InsertValueToDirectory {
+                key,
+                existing,
+                divider,
+                line,
+            } => writeln!(
+                f,
+                "Trying to insert value, into a Key that's a directory: {} holds {}{}",
+                key.to_string(*divider),
+                existing.to_string(*divider),
+                on_line(line)
+            ),
+            DBError::MultiKeyExtendValueKey {
+                key,
+                existing,
+                divider,
+                line,
+            } => writeln!(
+                f,
+                "Trying to insert MultiKey trying to extend Key that has a value: {} below the value of {}{}",
+                key.to_string(*divider),
+                existing.to_string(*divider),
+                on_line(line)
+            ),
+            DBError::CollationConflict { keys, divider } => {
+                let keys: Vec<String> = keys.iter().map(|key| key.to_string(*divider)).collect();
+                writeln!(
+                    f,
+                    "Keys would become the same under the collation: {}",
+                    keys.join(", ")
+                )
+            }
+            DBError::ConditionFailed => {
+                writeln!(
+                    f,
+                    "Conditional write failed, the current value did not match."
+                )
             }
-            DBError::MultiKeyExtendValueKey => {
+            DBError::KeyNotFound => writeln!(f, "Key was not found in the database."),
+            DBError::MergeConflict => writeln!(f, "Merge stopped on conflicting changes."),
+            DBError::PassphraseRequired => {
+                writeln!(f, "Database is encrypted, a passphrase is needed.")
+            }
+            DBError::DecryptionFailed => writeln!(
+                f,
+                "Could not decrypt the database, the passphrase is wrong or the file was changed."
+            ),
+            DBError::ChecksumMismatch => writeln!(
+                f,
+                "Database file does not match its checksum, it was truncated or changed by hand."
+            ),
+            DBError::SignatureInvalid => writeln!(
+                f,
+                "Database file is not signed, or not signed by the expected key."
+            ),
+            DBError::SigningKeyRequired => writeln!(
+                f,
+                "Database file is verified with a public key, changing it needs the signing key."
+            ),
+            DBError::SealingRequired => writeln!(
+                f,
+                "Database holds secret values, writing it needs a passphrase to seal them."
+            ),
+            DBError::SkippedLines { lines } => {
+                let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
                 writeln!(
                     f,
-                    "Trying to insert MultiKey trying to extend Key that has a value."
+                    "Saving would drop the lines the load skipped: {}",
+                    lines.join(", ")
                 )
             }
             DBError::KeyError(e) => writeln!(f, "{}", e),
@@ -34,9 +141,21 @@ impl fmt::Display for DBError {
 impl std::error::Error for DBError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
-            DBError::CorruptDBFile => None,
-            DBError::InsertValueToDirectory => None,
-            DBError::MultiKeyExtendValueKey => None,
+            DBError::CorruptDBFile { .. } => None,
+            DBError::ParseError { .. } => None,
+            DBError::InsertValueToDirectory { .. } => None,
+            DBError::MultiKeyExtendValueKey { .. } => None,
+            DBError::CollationConflict { .. } => None,
+            DBError::ConditionFailed => None,
+            DBError::KeyNotFound => None,
+            DBError::MergeConflict => None,
+            DBError::PassphraseRequired => None,
+            DBError::DecryptionFailed => None,
+            DBError::ChecksumMismatch => None,
+            DBError::SignatureInvalid => None,
+            DBError::SigningKeyRequired => None,
+            DBError::SealingRequired => None,
+            DBError::SkippedLines { .. } => None,
             DBError::KeyError(_) => None,
             // The cause is the underlying implementation error type. Is implicitly
             // cast to the trait object `&error::Error`. This works because the
@@ -46,6 +165,23 @@ impl std::error::Error for DBError {
     }
 }
 
+impl DBError {
+    /// Line of the database file the error was found on.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            DBError::CorruptDBFile { line }
+            | DBError::InsertValueToDirectory { line, .. }
+            | DBError::MultiKeyExtendValueKey { line, .. } => *line,
+            DBError::ParseError { line } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+fn on_line(line: &Option<usize>) -> String {
+    line.map_or_else(String::new, |line| format!(" on line {}", line))
+}
+
 impl From<Error> for DBError {
     fn from(error: Error) -> DBError {
         DBError::IOError(error)
@@ -61,11 +197,55 @@ impl From<KeyError> for DBError {
 impl PartialEq for DBError {
     fn eq(&self, other: &DBError) -> bool {
         match self {
-            DBError::CorruptDBFile => matches!(other, DBError::CorruptDBFile),
-            DBError::InsertValueToDirectory => matches!(other, DBError::InsertValueToDirectory),
-            DBError::MultiKeyExtendValueKey => matches!(other, DBError::MultiKeyExtendValueKey),
-            DBError::KeyError(_) => matches!(other, DBError::KeyError(_)),
-            DBError::IOError(_) => matches!(other, DBError::IOError(_)),
+            DBError::CorruptDBFile { line } => {
+                matches!(other, DBError::CorruptDBFile { line: other_line } if line == other_line)
+            }
+            DBError::ParseError { line } => {
+                matches!(other, DBError::ParseError { line: other_line } if line == other_line)
+            }
+            DBError::InsertValueToDirectory {
+                key,
+                existing,
+                divider,
+                line,
+            } => matches!(
+                other,
+                DBError::InsertValueToDirectory { key: other_key, existing: other_existing, divider: other_divider, line: other_line }
+                    if key == other_key && existing == other_existing && divider == other_divider && line == other_line
+            ),
+            DBError::MultiKeyExtendValueKey {
+                key,
+                existing,
+                divider,
+                line,
+            } => matches!(
+                other,
+                DBError::MultiKeyExtendValueKey { key: other_key, existing: other_existing, divider: other_divider, line: other_line }
+                    if key == other_key && existing == other_existing && divider == other_divider && line == other_line
+            ),
+            DBError::CollationConflict { keys, divider } => matches!(
+                other,
+                DBError::CollationConflict { keys: other_keys, divider: other_divider }
+                    if keys == other_keys && divider == other_divider
+            ),
+            DBError::ConditionFailed => matches!(other, DBError::ConditionFailed),
+            DBError::KeyNotFound => matches!(other, DBError::KeyNotFound),
+            DBError::MergeConflict => matches!(other, DBError::MergeConflict),
+            DBError::PassphraseRequired => matches!(other, DBError::PassphraseRequired),
+            DBError::DecryptionFailed => matches!(other, DBError::DecryptionFailed),
+            DBError::ChecksumMismatch => matches!(other, DBError::ChecksumMismatch),
+            DBError::SignatureInvalid => matches!(other, DBError::SignatureInvalid),
+            DBError::SigningKeyRequired => matches!(other, DBError::SigningKeyRequired),
+            DBError::SealingRequired => matches!(other, DBError::SealingRequired),
+            DBError::SkippedLines { lines } => {
+                matches!(other, DBError::SkippedLines { lines: other_lines } if lines == other_lines)
+            }
+            DBError::KeyError(error) => {
+                matches!(other, DBError::KeyError(other_error) if error == other_error)
+            }
+            DBError::IOError(error) => {
+                matches!(other, DBError::IOError(other_error) if error.kind() == other_error.kind())
+            }
         }
     }
 }
@@ -78,7 +258,7 @@ pub enum KeyError {
 
 impl fmt::Display for KeyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &*self {
+        match self {
             KeyError::ParseError => writeln!(f, "Could not parse input"),
             KeyError::NoKey => writeln!(f, "Input Key had a size of 0"),
         }
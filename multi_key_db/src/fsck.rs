@@ -0,0 +1,172 @@
+use crate::database::Database;
+use crate::error::DBError;
+use crate::key::Key;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Write;
+use std::str::FromStr;
+
+type Result<T> = std::result::Result<T, DBError>;
+
+/// What is wrong with a line of a database file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProblemKind {
+    /// No tab between key and value.
+    MalformedLine,
+    /// A line starting with a tab that follows no value.
+    OrphanContinuation,
+    /// A split: line that does not hold exactly one divider, or a second one.
+    BadSplitHeader,
+    /// An order: line naming no key order.
+    BadOrderHeader,
+    /// A collation: line naming no collation.
+    BadCollationHeader,
+    BadKey,
+    /// The value does not parse into the value type.
+    BadValue,
+    /// The key appears again further down, the later line wins.
+    DuplicateKey {
+        replaced_on: usize,
+    },
+    /// The key holds keys below it from earlier lines, existing is the first.
+    ValueOnParent {
+        existing: String,
+    },
+    /// A parent of the key holds a value from an earlier line, existing is that parent.
+    KeyBelowValue {
+        existing: String,
+    },
+    /// The checksum line does not match the rest of the file.
+    ChecksumMismatch,
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProblemKind::MalformedLine => write!(f, "no tab between key and value"),
+            ProblemKind::OrphanContinuation => write!(f, "continuation line without a value"),
+            ProblemKind::BadSplitHeader => write!(f, "split: needs exactly one divider"),
+            ProblemKind::BadOrderHeader => write!(f, "order: needs sorted or insertion"),
+            ProblemKind::BadCollationHeader => write!(f, "collation: names no collation"),
+            ProblemKind::BadKey => write!(f, "key does not parse"),
+            ProblemKind::BadValue => write!(f, "value does not parse"),
+            ProblemKind::DuplicateKey { replaced_on } => {
+                write!(f, "duplicate key, replaced on line {}", replaced_on)
+            }
+            ProblemKind::ValueOnParent { existing } => {
+                write!(f, "key already holds keys below it, like {}", existing)
+            }
+            ProblemKind::KeyBelowValue { existing } => {
+                write!(f, "key is below the value of {}", existing)
+            }
+            ProblemKind::ChecksumMismatch => write!(f, "checksum does not match the file"),
+        }
+    }
+}
+
+/// A rejected entry of a database file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// Line number starting at 1, the first line of the entry.
+    pub line: usize,
+    pub kind: ProblemKind,
+    /// The lines of the entry as they are in the file.
+    pub text: String,
+    /// Comments and blank lines right before the entry.
+    pub comments: Vec<String>,
+}
+
+impl Problem {
+    /// The key as it is written on the line, unless the line has no value.
+    pub fn key(&self) -> Option<&str> {
+        let head = self.text.lines().next()?;
+        head.split_once('\t').map(|(key, _)| key)
+    }
+
+    /// The line loading the file fails on, a duplicate key fails where it
+    /// appears again.
+    pub fn failing_line(&self) -> usize {
+        match self.kind {
+            ProblemKind::DuplicateKey { replaced_on } => replaced_on,
+            _ => self.line,
+        }
+    }
+
+    /// The error loading the file fails with, keys split at divider.
+    /// A problem whose keys do not parse only names its line.
+    pub fn error(&self, divider: char) -> DBError {
+        let line = Some(self.failing_line());
+        let keys = |existing: &str| {
+            let key = Key::new_from_str(self.key()?, divider).ok()?;
+            Some((key, Key::new_from_str(existing, divider).ok()?))
+        };
+        match &self.kind {
+            ProblemKind::ChecksumMismatch => DBError::ChecksumMismatch,
+            ProblemKind::BadKey | ProblemKind::BadValue => DBError::ParseError { line: self.line },
+            ProblemKind::ValueOnParent { existing } => match keys(existing) {
+                Some((key, existing)) => DBError::InsertValueToDirectory {
+                    key,
+                    existing,
+                    divider,
+                    line,
+                },
+                None => DBError::CorruptDBFile { line },
+            },
+            ProblemKind::KeyBelowValue { existing } => match keys(existing) {
+                Some((key, existing)) => DBError::MultiKeyExtendValueKey {
+                    key,
+                    existing,
+                    divider,
+                    line,
+                },
+                None => DBError::CorruptDBFile { line },
+            },
+            _ => DBError::CorruptDBFile { line },
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// Result of Database::fsck, every entry that loaded and every problem.
+#[derive(Debug)]
+pub struct Fsck<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr,
+    V: Default + Debug + FromStr,
+{
+    pub database: Database<K, V>,
+    /// Sorted by line.
+    pub problems: Vec<Problem>,
+}
+
+impl<K, V> Fsck<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr,
+    V: Default + Debug + FromStr,
+{
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Writes the rejected lines, each entry after its comments and a
+    /// comment naming its problem.
+    pub fn write_rejected<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        for problem in &self.problems {
+            for comment in &problem.comments {
+                writeln!(writer, "{}", comment)?;
+            }
+            writeln!(writer, "# {}", problem)?;
+            writeln!(writer, "{}", problem.text)?;
+        }
+        Ok(writer.flush()?)
+    }
+}
@@ -46,6 +46,14 @@ where
 
         result
     }
+
+    /// The same segments as strings, the way errors carry keys.
+    pub fn to_string_key(&self) -> Key<String> {
+        Key {
+            multi_key: self.multi_key.iter().map(ToString::to_string).collect(),
+            parent_depth: 0,
+        }
+    }
 }
 
 impl<K> Key<K>
@@ -74,6 +82,15 @@ where
     pub fn size(&self) -> usize {
         self.multi_key.len()
     }
+
+    pub(crate) fn segments(&self) -> &[K] {
+        &self.multi_key
+    }
+
+    /// True when every segment of prefix matches the start of this key.
+    pub fn starts_with(&self, prefix: &Key<K>) -> bool {
+        self.multi_key.starts_with(&prefix.multi_key)
+    }
 }
 impl<K> Key<K>
 where
@@ -143,10 +160,7 @@ where
             result.push(self.multi_key[i].clone());
         }
         self.increment_parent();
-        match Key::new_from_vec(result) {
-            Ok(key) => Some(key),
-            Err(_) => None,
-        }
+        Key::new_from_vec(result).ok()
     }
 }
 
@@ -183,7 +197,7 @@ where
     K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
 {
     fn partial_cmp(&self, other: &Key<K>) -> std::option::Option<std::cmp::Ordering> {
-        self.multi_key.partial_cmp(&other.multi_key)
+        Some(self.cmp(other))
     }
 }
 
@@ -264,4 +278,15 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn key_starts_with() -> Result<(), KeyError> {
+        let key: Key<String> = Key::new_from_str("1.2.3", '.')?;
+        assert!(key.starts_with(&Key::new_from_str("1.2", '.')?));
+        assert!(key.starts_with(&Key::new_from_str("1.2.3", '.')?));
+        assert!(!key.starts_with(&Key::new_from_str("1.3", '.')?));
+        assert!(!key.starts_with(&Key::new_from_str("1.2.3.4", '.')?));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,167 @@
+use crate::error::KeyError;
+use crate::key::Key;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// Order of the children of every parent of a database.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum KeyOrder {
+    /// Sorted by the key segments.
+    #[default]
+    Sorted,
+    /// In the order the keys were first inserted.
+    Insertion,
+}
+
+impl KeyOrder {
+    pub const NAMES: [&'static str; 2] = ["sorted", "insertion"];
+}
+
+impl FromStr for KeyOrder {
+    type Err = KeyError;
+
+    fn from_str(order: &str) -> std::result::Result<Self, Self::Err> {
+        match order {
+            "sorted" => Ok(KeyOrder::Sorted),
+            "insertion" => Ok(KeyOrder::Insertion),
+            _ => Err(KeyError::ParseError),
+        }
+    }
+}
+
+impl fmt::Display for KeyOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            KeyOrder::Sorted => "sorted",
+            KeyOrder::Insertion => "insertion",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Comments, blank lines and the order of the keys of a loaded file,
+/// so saving it again only changes the lines that changed.
+#[derive(Debug, Clone)]
+pub struct Layout<K>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    header: Vec<String>,
+    // Lines right before each key, every loaded key has an entry
+    comments: BTreeMap<Key<K>, Vec<String>>,
+    order: Vec<Key<K>>,
+    footer: Vec<String>,
+}
+
+impl<K> Default for Layout<K>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    fn default() -> Self {
+        Layout {
+            header: Vec::new(),
+            comments: BTreeMap::new(),
+            order: Vec::new(),
+            footer: Vec::new(),
+        }
+    }
+}
+
+impl<K> Layout<K>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Clone,
+{
+    /// Lines at the top of the file, up to the last blank line before the first key.
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    /// Comments and blank lines right before key.
+    pub fn comments(&self, key: &Key<K>) -> &[String] {
+        self.comments.get(key).map_or(&[], |comments| comments)
+    }
+
+    /// Lines after the last key.
+    pub fn footer(&self) -> &[String] {
+        &self.footer
+    }
+
+    /// Adds the next key of the file, a key seen before moves to the end.
+    pub(crate) fn push(&mut self, key: Key<K>, mut lines: Vec<String>) {
+        if self.order.is_empty() {
+            // A blank line ends the header
+            if let Some(end) = lines.iter().rposition(|line| line.is_empty()) {
+                self.header = lines.drain(..=end).collect();
+            }
+        }
+        if self.comments.insert(key.clone(), lines).is_some() {
+            self.order.retain(|other| other != &key);
+        }
+        self.order.push(key);
+    }
+
+    pub(crate) fn set_footer(&mut self, lines: Vec<String>) {
+        self.footer = lines;
+    }
+
+    /// Forgets the file order, the comments stay with their keys.
+    pub(crate) fn clear_order(&mut self) {
+        self.order.clear();
+    }
+
+    /// Stores the keys of the comments again as collate returns them and
+    /// forgets the file order. Of keys that become the same the first one
+    /// keeps its comments.
+    pub(crate) fn collate<F>(&mut self, collate: F)
+    where
+        F: Fn(&Key<K>) -> Key<K>,
+    {
+        let mut comments = BTreeMap::new();
+        for (key, lines) in std::mem::take(&mut self.comments) {
+            comments.entry(collate(&key)).or_insert(lines);
+        }
+        self.comments = comments;
+        self.order.clear();
+    }
+
+    /// Forgets key and the keys below it.
+    pub(crate) fn remove(&mut self, key: &Key<K>) {
+        self.comments.retain(|other, _| !other.starts_with(key));
+        self.order.retain(|other| !other.starts_with(key));
+    }
+
+    /// The pairs in file order. A key new since the load goes right after
+    /// the closest key sorting before it by compare.
+    pub(crate) fn sort<'a, V, C>(
+        &self,
+        pairs: Vec<(Key<K>, &'a V)>,
+        compare: C,
+    ) -> Vec<(Key<K>, &'a V)>
+    where
+        C: Fn(&Key<K>, &Key<K>) -> Ordering,
+    {
+        if self.order.is_empty() {
+            return pairs;
+        }
+        let mut values: BTreeMap<Key<K>, &V> = pairs.into_iter().collect();
+        let mut sorted: Vec<(Key<K>, &V)> = self
+            .order
+            .iter()
+            .filter_map(|key| values.remove_entry(key))
+            .collect();
+        for (key, value) in values {
+            let index = sorted
+                .iter()
+                .enumerate()
+                .filter(|(_, (other, _))| compare(other, &key) == Ordering::Less)
+                .max_by(|(_, (a, _)), (_, (b, _))| compare(a, b))
+                .map_or(0, |(index, _)| index + 1);
+            sorted.insert(index, (key, value));
+        }
+        sorted
+    }
+}
@@ -1,4 +1,19 @@
+pub mod checksum;
+pub mod collation;
 pub mod database;
+pub mod diff;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod error;
+pub mod fsck;
 pub mod key;
-pub(crate) mod node;
+pub mod layout;
+pub mod load;
+pub mod merge;
+pub mod node;
+pub mod patch;
+#[cfg(feature = "cli-features")]
+pub mod render;
+pub mod search;
+#[cfg(feature = "signing")]
+pub mod signing;
@@ -0,0 +1,85 @@
+use crate::error::KeyError;
+use crate::fsck::{Problem, ProblemKind};
+use std::fmt;
+use std::str::FromStr;
+
+/// How loading a database file treats problems of its lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LoadMode {
+    /// Every problem fails, duplicate keys included.
+    Strict,
+    /// A later value of a key replaces an earlier one, anything else fails.
+    #[default]
+    Normal,
+    /// Lines with problems are skipped and reported.
+    Lenient,
+}
+
+impl LoadMode {
+    pub const NAMES: [&'static str; 3] = ["strict", "normal", "lenient"];
+
+    /// False for problems the mode only reports.
+    /// A checksum that does not match, like after a truncation or an edit by
+    /// hand, only passes in lenient mode.
+    pub fn fails_on(&self, problem: &Problem) -> bool {
+        match (self, &problem.kind) {
+            (LoadMode::Strict, _) => true,
+            (LoadMode::Normal, ProblemKind::DuplicateKey { .. }) => false,
+            (LoadMode::Normal, _) => true,
+            (LoadMode::Lenient, _) => false,
+        }
+    }
+}
+
+impl FromStr for LoadMode {
+    type Err = KeyError;
+
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        match mode {
+            "strict" => Ok(LoadMode::Strict),
+            "normal" => Ok(LoadMode::Normal),
+            "lenient" => Ok(LoadMode::Lenient),
+            _ => Err(KeyError::ParseError),
+        }
+    }
+}
+
+impl fmt::Display for LoadMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LoadMode::Strict => "strict",
+            LoadMode::Normal => "normal",
+            LoadMode::Lenient => "lenient",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Settings for Database::load.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LoadOptions {
+    pub mode: LoadMode,
+}
+
+/// Problems a load skipped or let pass, sorted by line.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LoadReport {
+    pub warnings: Vec<Problem>,
+}
+
+impl LoadReport {
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// Lines of the entries the database lost, a duplicate key only lost
+    /// the value it replaced. A checksum that does not match counts too,
+    /// the file may have lost lines before the load.
+    pub fn skipped_lines(&self) -> Vec<usize> {
+        self.warnings
+            .iter()
+            .filter(|warning| !matches!(warning.kind, ProblemKind::DuplicateKey { .. }))
+            .map(|warning| warning.line)
+            .collect()
+    }
+}
@@ -0,0 +1,94 @@
+use crate::database::Database;
+use crate::error::KeyError;
+use crate::key::Key;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::str::FromStr;
+
+/// Which side wins when both sides of a merge changed the same key.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MergeStrategy {
+    Ours,
+    Theirs,
+    /// Conflicting keys keep their base state, the caller has to fail.
+    #[default]
+    Fail,
+}
+
+impl MergeStrategy {
+    pub const NAMES: [&'static str; 3] = ["ours", "theirs", "fail"];
+}
+
+impl FromStr for MergeStrategy {
+    type Err = KeyError;
+
+    fn from_str(strategy: &str) -> std::result::Result<Self, Self::Err> {
+        match strategy {
+            "ours" => Ok(MergeStrategy::Ours),
+            "theirs" => Ok(MergeStrategy::Theirs),
+            "fail" => Ok(MergeStrategy::Fail),
+            _ => Err(KeyError::ParseError),
+        }
+    }
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MergeStrategy::Ours => "ours",
+            MergeStrategy::Theirs => "theirs",
+            MergeStrategy::Fail => "fail",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A key both sides of a merge changed in different ways.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    /// Both sides changed the value, None where a side has no value.
+    Value {
+        key: Key<K>,
+        base: Option<V>,
+        ours: Option<V>,
+        theirs: Option<V>,
+    },
+    /// One side holds a value at key while the other holds values below it.
+    /// The whole subtree is taken from the winning side.
+    Structure { key: Key<K> },
+}
+
+impl<K, V> Conflict<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    pub fn key(&self) -> &Key<K> {
+        match self {
+            Conflict::Value { key, .. } | Conflict::Structure { key } => key,
+        }
+    }
+}
+
+/// Result of Database::merge, conflicts are listed whatever the strategy.
+#[derive(Debug)]
+pub struct Merged<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr,
+    V: Default + Debug + FromStr,
+{
+    pub database: Database<K, V>,
+    pub conflicts: Vec<Conflict<K, V>>,
+}
+
+impl<K, V> Merged<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr,
+    V: Default + Debug + FromStr,
+{
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
@@ -4,7 +4,7 @@ use std::fmt::Debug;
 use std::hash::Hash;
 use std::str::FromStr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NodeType<K, V>
 where
     K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr + Default,
@@ -0,0 +1,369 @@
+use crate::diff::Change;
+use crate::error::{DBError, KeyError};
+use crate::key::Key;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+type Result<T> = std::result::Result<T, DBError>;
+
+const SET: &str = "set";
+const REMOVE: &str = "remove";
+const MOVE: &str = "move";
+
+/// One step of a patch, each carries the value it expects to find.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    /// Sets key to value, old is the value it holds first, None for no value.
+    Set {
+        key: Key<K>,
+        old: Option<V>,
+        value: V,
+    },
+    /// Removes the value of key, which has to be old.
+    Remove { key: Key<K>, old: V },
+    /// Moves value from one key to another key without a value.
+    Move { from: Key<K>, to: Key<K>, value: V },
+}
+
+/// A reviewable set of changes, applied by Database::apply_patch.
+///
+/// Written as one block per operation. The first line holds set, remove or
+/// move and the keys, separated by tabs. The old value follows on a line
+/// starting with -, the new one with + and a moved one with =. Like the
+/// database file, further lines of a value are indented by a tab.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd,
+{
+    pub operations: Vec<Operation<K, V>>,
+}
+
+impl<K, V> Patch<K, V>
+where
+    K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Clone + FromStr + ToString,
+    V: FromStr + ToString + PartialEq,
+{
+    /// Turns the changes of Database::diff into operations. A value removed
+    /// from one key and added to another becomes a move, when no other key
+    /// loses or gains the same value.
+    pub fn from_changes(changes: Vec<Change<K, V>>) -> Patch<K, V> {
+        // Keys that lost and number of keys that gained every value, by its text
+        let mut values: BTreeMap<String, (Vec<&Key<K>>, usize)> = BTreeMap::new();
+        for change in &changes {
+            match change {
+                Change::Removed { key, value } => {
+                    values.entry(value.to_string()).or_default().0.push(key)
+                }
+                Change::Added { value, .. } => values.entry(value.to_string()).or_default().1 += 1,
+                _ => (),
+            }
+        }
+        // From keys by their to key
+        let mut moves: BTreeMap<Key<K>, Key<K>> = BTreeMap::new();
+        for change in &changes {
+            if let Change::Added { key, value } = change {
+                if let Some(([from], 1)) = values
+                    .get(&value.to_string())
+                    .map(|(removed, added)| (removed.as_slice(), *added))
+                {
+                    moves.insert(key.clone(), (*from).clone());
+                }
+            }
+        }
+        let moved: BTreeSet<Key<K>> = moves.values().cloned().collect();
+
+        let mut operations = Vec::new();
+        for change in changes {
+            let operation = match change {
+                Change::Added { key, value } => match moves.remove(&key) {
+                    Some(from) => Operation::Move {
+                        from,
+                        to: key,
+                        value,
+                    },
+                    None => Operation::Set {
+                        key,
+                        old: None,
+                        value,
+                    },
+                },
+                Change::Removed { key, .. } if moved.contains(&key) => continue,
+                Change::Removed { key, value } => Operation::Remove { key, old: value },
+                Change::Changed { key, before, after } => Operation::Set {
+                    key,
+                    old: Some(before),
+                    value: after,
+                },
+                // The values below the parent side follow as their own changes
+                Change::TypeChanged {
+                    key,
+                    before: Some(before),
+                    ..
+                } => Operation::Remove { key, old: before },
+                Change::TypeChanged {
+                    key,
+                    after: Some(after),
+                    ..
+                } => Operation::Set {
+                    key,
+                    old: None,
+                    value: after,
+                },
+                Change::TypeChanged { .. } => continue,
+            };
+            operations.push(operation);
+        }
+        Patch { operations }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Puts prefix in front of every key, to apply a patch below a key.
+    pub fn prefixed(self, prefix: &Key<K>) -> Patch<K, V> {
+        let prefix_key = |key: Key<K>| {
+            let mut prefixed = prefix.clone();
+            prefixed.add(&key);
+            prefixed
+        };
+        let operations = self
+            .operations
+            .into_iter()
+            .map(|operation| match operation {
+                Operation::Set { key, old, value } => Operation::Set {
+                    key: prefix_key(key),
+                    old,
+                    value,
+                },
+                Operation::Remove { key, old } => Operation::Remove {
+                    key: prefix_key(key),
+                    old,
+                },
+                Operation::Move { from, to, value } => Operation::Move {
+                    from: prefix_key(from),
+                    to: prefix_key(to),
+                    value,
+                },
+            })
+            .collect();
+        Patch { operations }
+    }
+
+    pub fn read<R>(reader: &mut R, divider: char) -> Result<Patch<K, V>>
+    where
+        R: Read,
+    {
+        // The operation line and its value lines, marker and value
+        let mut blocks: Vec<(String, Vec<(char, String)>)> = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            if let Some(continuation) = line.strip_prefix('\t') {
+                let (_, values) = blocks.last_mut().ok_or(KeyError::ParseError)?;
+                let (_, value) = values.last_mut().ok_or(KeyError::ParseError)?;
+                value.push('\n');
+                value.push_str(continuation);
+            } else if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if let Some(marker @ ('-' | '+' | '=')) = line.chars().next() {
+                let (_, values) = blocks.last_mut().ok_or(KeyError::ParseError)?;
+                let value = line[1..].strip_prefix(' ').unwrap_or(&line[1..]);
+                values.push((marker, value.into()));
+            } else {
+                blocks.push((line, Vec::new()));
+            }
+        }
+
+        let key = |key: &str| Key::new_from_str(key, divider);
+        let value = |value: &str| V::from_str(value).map_err(|_| KeyError::ParseError);
+        let mut operations = Vec::new();
+        for (line, values) in blocks {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let values: Vec<(char, &str)> = values
+                .iter()
+                .map(|(marker, value)| (*marker, value.as_str()))
+                .collect();
+            let operation = match (fields.as_slice(), values.as_slice()) {
+                ([SET, name], [('+', new)]) => Operation::Set {
+                    key: key(name)?,
+                    old: None,
+                    value: value(new)?,
+                },
+                ([SET, name], [('-', old), ('+', new)]) => Operation::Set {
+                    key: key(name)?,
+                    old: Some(value(old)?),
+                    value: value(new)?,
+                },
+                ([REMOVE, name], [('-', old)]) => Operation::Remove {
+                    key: key(name)?,
+                    old: value(old)?,
+                },
+                ([MOVE, from, to], [('=', moved)]) => Operation::Move {
+                    from: key(from)?,
+                    to: key(to)?,
+                    value: value(moved)?,
+                },
+                _ => return Err(KeyError::ParseError.into()),
+            };
+            operations.push(operation);
+        }
+        Ok(Patch { operations })
+    }
+
+    pub fn write<W>(&self, writer: &mut W, divider: char) -> Result<()>
+    where
+        W: Write,
+    {
+        let value = |marker: char, value: &V| {
+            format!("{} {}\n", marker, value.to_string().replace('\n', "\n\t"))
+        };
+        let mut contents = String::new();
+        for operation in &self.operations {
+            match operation {
+                Operation::Set {
+                    key,
+                    old,
+                    value: new,
+                } => {
+                    contents.push_str(&format!("{}\t{}\n", SET, key.to_string(divider)));
+                    if let Some(old) = old {
+                        contents.push_str(&value('-', old));
+                    }
+                    contents.push_str(&value('+', new));
+                }
+                Operation::Remove { key, old } => {
+                    contents.push_str(&format!("{}\t{}\n", REMOVE, key.to_string(divider)));
+                    contents.push_str(&value('-', old));
+                }
+                Operation::Move {
+                    from,
+                    to,
+                    value: moved,
+                } => {
+                    contents.push_str(&format!(
+                        "{}\t{}\t{}\n",
+                        MOVE,
+                        from.to_string(divider),
+                        to.to_string(divider)
+                    ));
+                    contents.push_str(&value('=', moved));
+                }
+            }
+        }
+        writer.write_all(contents.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::patch::*;
+    use std::io::Cursor;
+
+    fn key(key: &str) -> Key<String> {
+        Key::new_from_str(key, '.').unwrap()
+    }
+
+    #[test]
+    fn patch_from_changes() {
+        let changes: Vec<Change<String, String>> = vec![
+            Change::Removed {
+                key: key("app.name"),
+                value: "Example".into(),
+            },
+            Change::Added {
+                key: key("app.title"),
+                value: "Example".into(),
+            },
+            Change::Removed {
+                key: key("a"),
+                value: "true".into(),
+            },
+            Change::Removed {
+                key: key("b"),
+                value: "true".into(),
+            },
+            Change::Added {
+                key: key("c"),
+                value: "true".into(),
+            },
+            Change::Changed {
+                key: key("host"),
+                before: "old".into(),
+                after: "new".into(),
+            },
+        ];
+        let patch = Patch::from_changes(changes);
+        assert_eq!(
+            vec![
+                Operation::Move {
+                    from: key("app.name"),
+                    to: key("app.title"),
+                    value: "Example".to_string()
+                },
+                Operation::Remove {
+                    key: key("a"),
+                    old: "true".into()
+                },
+                Operation::Remove {
+                    key: key("b"),
+                    old: "true".into()
+                },
+                Operation::Set {
+                    key: key("c"),
+                    old: None,
+                    value: "true".into()
+                },
+                Operation::Set {
+                    key: key("host"),
+                    old: Some("old".into()),
+                    value: "new".into()
+                },
+            ],
+            patch.operations
+        );
+    }
+
+    #[test]
+    fn patch_write_and_read() {
+        let patch: Patch<String, String> = Patch {
+            operations: vec![
+                Operation::Set {
+                    key: key("cert"),
+                    old: Some("a\nb".into()),
+                    value: "- c".into(),
+                },
+                Operation::Remove {
+                    key: key("debug"),
+                    old: "true".into(),
+                },
+                Operation::Move {
+                    from: key("name"),
+                    to: key("title"),
+                    value: "".into(),
+                },
+            ],
+        };
+        let mut contents = Vec::new();
+        patch.write(&mut contents, '.').unwrap();
+        assert_eq!(
+            "set\tcert\n- a\n\tb\n+ - c\nremove\tdebug\n- true\nmove\tname\ttitle\n= \n",
+            String::from_utf8(contents.clone()).unwrap()
+        );
+        assert_eq!(patch, Patch::read(&mut Cursor::new(contents), '.').unwrap());
+
+        let broken = Patch::<String, String>::read(&mut Cursor::new("set\tkey\n- old\n"), '.');
+        assert_eq!(
+            DBError::KeyError(KeyError::ParseError),
+            broken.err().unwrap()
+        );
+    }
+}
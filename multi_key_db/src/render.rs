@@ -0,0 +1,529 @@
+use crate::database::Database;
+use crate::error::DBError;
+use crate::error::KeyError;
+use crate::key::Key;
+use crate::node::NodeType;
+
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Write;
+use std::str::FromStr;
+
+use cli_table::{
+    format::{Align, Justify, Padding},
+    Cell, ColorChoice, Table,
+};
+
+type Result<T> = std::result::Result<T, DBError>;
+
+/// A key value pair converted to text, ready to be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Each segment of the multi key.
+    pub segments: Vec<String>,
+    /// The full key joined by the database divider.
+    pub key: String,
+    pub value: String,
+}
+
+/// Writes entries to any writer.
+/// Implement this to plug a custom output format into the database.
+pub trait Renderer {
+    fn render(&self, entries: &[Entry], writer: &mut dyn Write) -> Result<()>;
+}
+
+/// Built in output formats.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Yaml,
+    Csv,
+    Tsv,
+    Tree,
+    Markdown,
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 7] =
+        ["table", "json", "yaml", "csv", "tsv", "tree", "markdown"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = KeyError;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "tree" => Ok(OutputFormat::Tree),
+            "markdown" => Ok(OutputFormat::Markdown),
+            _ => Err(KeyError::ParseError),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Json => "json",
+            OutputFormat::Yaml => "yaml",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Tree => "tree",
+            OutputFormat::Markdown => "markdown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Renderer for OutputFormat {
+    fn render(&self, entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+        match self {
+            OutputFormat::Table => render_table(entries, writer),
+            OutputFormat::Json => render_json(entries, writer),
+            OutputFormat::Yaml => render_yaml(entries, writer),
+            OutputFormat::Csv => render_csv(entries, writer),
+            OutputFormat::Tsv => render_tsv(entries, writer),
+            OutputFormat::Tree => {
+                TreeRenderer::default().write_root(".", &Branch::from_entries(entries), writer)
+            }
+            OutputFormat::Markdown => render_markdown(entries, writer),
+        }
+    }
+}
+
+fn render_table(entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+    let mut contents = Vec::new();
+    for entry in entries {
+        contents.push(vec![
+            entry
+                .key
+                .as_str()
+                .cell()
+                .align(Align::Center)
+                .padding(Padding::builder().right(1).build()),
+            entry.value.as_str().cell().align(Align::Center),
+        ])
+    }
+
+    let table = contents
+        .table()
+        .title(vec![
+            "Key".cell().justify(Justify::Center).align(Align::Center),
+            "Value".cell().justify(Justify::Center).align(Align::Center),
+        ])
+        .color_choice(ColorChoice::Never);
+    writeln!(writer, "{}", table.display()?)?;
+    Ok(())
+}
+
+fn render_csv(entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, "key,value")?;
+    for entry in entries {
+        writeln!(
+            writer,
+            "{},{}",
+            csv_field(&entry.key),
+            csv_field(&entry.value)
+        )?;
+    }
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+fn render_tsv(entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+    for entry in entries {
+        writeln!(writer, "{}\t{}", entry.key, entry.value)?;
+    }
+    Ok(())
+}
+
+fn render_markdown(entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+    writeln!(writer, "| Key | Value |")?;
+    writeln!(writer, "| --- | --- |")?;
+    for entry in entries {
+        writeln!(
+            writer,
+            "| {} | {} |",
+            markdown_cell(&entry.key),
+            markdown_cell(&entry.value)
+        )?;
+    }
+    Ok(())
+}
+
+fn markdown_cell(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', "<br>")
+}
+
+fn render_json(entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+    let root = Branch::from_entries(entries);
+    write_json(&root, 0, writer)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+fn write_json(branch: &Branch, depth: usize, writer: &mut dyn Write) -> Result<()> {
+    if branch.children.is_empty() {
+        write!(writer, "{{}}")?;
+        return Ok(());
+    }
+    writeln!(writer, "{{")?;
+    let indent = "  ".repeat(depth + 1);
+    for (index, (segment, child)) in branch.children.iter().enumerate() {
+        write!(writer, "{}{}: ", indent, json_string(segment))?;
+        match &child.value {
+            Some(value) => write!(writer, "{}", json_string(value))?,
+            None => write_json(child, depth + 1, writer)?,
+        }
+        if index + 1 < branch.children.len() {
+            write!(writer, ",")?;
+        }
+        writeln!(writer)?;
+    }
+    write!(writer, "{}}}", "  ".repeat(depth))?;
+    Ok(())
+}
+
+fn json_string(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn render_yaml(entries: &[Entry], writer: &mut dyn Write) -> Result<()> {
+    let root = Branch::from_entries(entries);
+    if root.children.is_empty() {
+        writeln!(writer, "{{}}")?;
+        return Ok(());
+    }
+    write_yaml(&root, 0, writer)
+}
+
+fn write_yaml(branch: &Branch, depth: usize, writer: &mut dyn Write) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    for (segment, child) in &branch.children {
+        match &child.value {
+            Some(value) => writeln!(
+                writer,
+                "{}{}: {}",
+                indent,
+                yaml_scalar(segment),
+                yaml_scalar(value)
+            )?,
+            None => {
+                writeln!(writer, "{}{}:", indent, yaml_scalar(segment))?;
+                write_yaml(child, depth + 1, writer)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Plain scalars are only used when YAML would read them back as the same string,
+/// so text starting with a digit, like numbers, dates and hex, is quoted.
+fn yaml_scalar(text: &str) -> String {
+    let plain = !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/'))
+        && !text.starts_with('-')
+        && !text.starts_with('.')
+        && text.parse::<f64>().is_err()
+        && !matches!(
+            text.to_lowercase().as_str(),
+            "true" | "false" | "yes" | "no" | "on" | "off" | "null" | "y" | "n"
+        );
+    if plain {
+        text.into()
+    } else {
+        json_string(text)
+    }
+}
+
+/// Draws the hierarchy of a database like the Unix tree command.
+/// Parents are shown with their child count, values are truncated to width.
+#[derive(Debug, Default, Clone)]
+pub struct TreeRenderer {
+    /// Levels below the root to draw, None draws every level.
+    pub depth: Option<usize>,
+    /// Maximum line width in characters, None never truncates.
+    pub width: Option<usize>,
+}
+
+impl TreeRenderer {
+    /// Renders the subtree at prefix, or the whole database without one.
+    pub fn render<K, V, W>(
+        &self,
+        database: &Database<K, V>,
+        prefix: Option<&Key<K>>,
+        writer: &mut W,
+    ) -> Result<()>
+    where
+        K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr + ToString,
+        V: Default + Debug + FromStr + ToString,
+        W: Write,
+    {
+        let divider = database.divider();
+        match prefix {
+            None => self.write_root(".", &Branch::from_database(database), writer)?,
+            Some(key) => match database.get_node(key).ok_or(DBError::KeyNotFound)? {
+                NodeType::Parent(parent) => {
+                    let root = format!("{} ({})", key.to_string(divider), parent.map.len());
+                    self.write_root(&root, &Branch::from_database(parent), writer)?;
+                }
+                NodeType::Value(value) => {
+                    let line = format!("{}: {}", key.to_string(divider), value.to_string());
+                    writeln!(writer, "{}", self.fit(line))?;
+                }
+            },
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Every tree starts with its root line, "." for all keys.
+    fn write_root(&self, root: &str, branch: &Branch, writer: &mut dyn Write) -> Result<()> {
+        writeln!(writer, "{}", root)?;
+        self.write_branch(branch, "", 1, writer)
+    }
+
+    fn write_branch(
+        &self,
+        branch: &Branch,
+        prefix: &str,
+        level: usize,
+        writer: &mut dyn Write,
+    ) -> Result<()> {
+        for (index, (segment, child)) in branch.children.iter().enumerate() {
+            let last = index + 1 == branch.children.len();
+            let connector = if last { "└── " } else { "├── " };
+            match &child.value {
+                Some(value) => {
+                    let line = format!("{}{}{}: {}", prefix, connector, segment, value);
+                    writeln!(writer, "{}", self.fit(line))?;
+                }
+                None => {
+                    let line = format!(
+                        "{}{}{} ({})",
+                        prefix,
+                        connector,
+                        segment,
+                        child.children.len()
+                    );
+                    writeln!(writer, "{}", self.fit(line))?;
+                    if self.depth.is_none_or(|depth| level < depth) {
+                        let child_prefix =
+                            format!("{}{}", prefix, if last { "    " } else { "│   " });
+                        self.write_branch(child, &child_prefix, level + 1, writer)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fit(&self, line: String) -> String {
+        match self.width {
+            Some(width) if width > 0 && line.chars().count() > width => {
+                let mut fitted: String = line.chars().take(width - 1).collect();
+                fitted.push('…');
+                fitted
+            }
+            _ => line,
+        }
+    }
+}
+
+/// Entries regrouped by segment, used by the nested formats.
+#[derive(Default)]
+struct Branch {
+    children: Vec<(String, Branch)>,
+    value: Option<String>,
+}
+
+impl Branch {
+    fn from_entries(entries: &[Entry]) -> Branch {
+        let mut root = Branch::default();
+        for entry in entries {
+            let mut branch = &mut root;
+            for segment in &entry.segments {
+                let index = match branch.children.iter().position(|(s, _)| s == segment) {
+                    Some(index) => index,
+                    None => {
+                        branch.children.push((segment.clone(), Branch::default()));
+                        branch.children.len() - 1
+                    }
+                };
+                branch = &mut branch.children[index].1;
+            }
+            branch.value = Some(entry.value.clone());
+        }
+        root
+    }
+
+    /// Keeps empty parents and the key order of the database, unlike its entries.
+    fn from_database<K, V>(database: &Database<K, V>) -> Branch
+    where
+        K: Debug + Eq + PartialEq + Hash + Ord + PartialOrd + Default + Clone + FromStr + ToString,
+        V: Default + Debug + FromStr + ToString,
+    {
+        let divider = database.divider();
+        let children = database
+            .nodes()
+            .into_iter()
+            .map(|(segment, node)| {
+                let child = match node {
+                    NodeType::Parent(parent) => Branch::from_database(parent),
+                    NodeType::Value(value) => Branch {
+                        children: Vec::new(),
+                        value: Some(value.to_string()),
+                    },
+                };
+                (segment.to_string(divider), child)
+            })
+            .collect();
+        Branch {
+            children,
+            value: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                segments: vec!["work".into(), "team".into(), "git".into()],
+                key: "work.team.git".into(),
+                value: "github.com/example-repo".into(),
+            },
+            Entry {
+                segments: vec!["work".into(), "name".into()],
+                key: "work.name".into(),
+                value: "a \"quoted\", value".into(),
+            },
+        ]
+    }
+
+    fn render(format: OutputFormat) -> String {
+        let mut output = Vec::new();
+        format.render(&entries(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn render_json() {
+        assert_eq!(
+            "{\n  \"work\": {\n    \"team\": {\n      \"git\": \"github.com/example-repo\"\n    },\n    \"name\": \"a \\\"quoted\\\", value\"\n  }\n}\n",
+            render(OutputFormat::Json)
+        );
+    }
+
+    #[test]
+    fn render_yaml() {
+        assert_eq!(
+            "work:\n  team:\n    git: github.com/example-repo\n  name: \"a \\\"quoted\\\", value\"\n",
+            render(OutputFormat::Yaml)
+        );
+        assert_eq!("\"2024-01-01\"", yaml_scalar("2024-01-01"));
+        assert_eq!("\"0x1F\"", yaml_scalar("0x1F"));
+        assert_eq!("v2", yaml_scalar("v2"));
+    }
+
+    #[test]
+    fn render_csv() {
+        assert_eq!(
+            "key,value\nwork.team.git,github.com/example-repo\nwork.name,\"a \"\"quoted\"\", value\"\n",
+            render(OutputFormat::Csv)
+        );
+    }
+
+    #[test]
+    fn render_tree() {
+        assert_eq!(
+            ".\n└── work (2)\n    ├── team (1)\n    │   └── git: github.com/example-repo\n    └── name: a \"quoted\", value\n",
+            render(OutputFormat::Tree)
+        );
+    }
+
+    fn database() -> Database<String, String> {
+        let mut database = Database::new();
+        for entry in entries() {
+            let key = Key::new_from_vec(entry.segments).unwrap();
+            database.insert(key, entry.value).unwrap();
+        }
+        database
+    }
+
+    #[test]
+    fn tree_renderer() {
+        let mut output = Vec::new();
+        TreeRenderer::default()
+            .render(&database(), None, &mut output)
+            .unwrap();
+        assert_eq!(
+            ".\n└── work (2)\n    ├── name: a \"quoted\", value\n    └── team (1)\n        └── git: github.com/example-repo\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn tree_renderer_prefix_depth_width() {
+        let renderer = TreeRenderer {
+            depth: Some(1),
+            width: Some(16),
+        };
+        let mut output = Vec::new();
+        let prefix = Key::new_from_str("work", '.').unwrap();
+        renderer
+            .render(&database(), Some(&prefix), &mut output)
+            .unwrap();
+        assert_eq!(
+            "work (2)\n├── name: a \"qu…\n└── team (1)\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let missing = Key::new_from_str("missing", '.').unwrap();
+        let result = renderer.render(&database(), Some(&missing), &mut Vec::new());
+        assert_eq!(DBError::KeyNotFound, result.err().unwrap());
+    }
+
+    #[test]
+    fn output_format_from_str() {
+        for name in OutputFormat::NAMES.iter() {
+            let format: OutputFormat = name.parse().unwrap();
+            assert_eq!(*name, format.to_string());
+        }
+        assert_eq!(Err(KeyError::ParseError), "html".parse::<OutputFormat>());
+    }
+}
@@ -0,0 +1,77 @@
+use std::ops::Range;
+
+#[cfg(feature = "cli-features")]
+use regex::Regex;
+
+/// Which part of each key value pair a search looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SearchScope {
+    Keys,
+    Values,
+    #[default]
+    Both,
+}
+
+/// Something Database::search can look for in keys and values.
+pub trait SearchPattern {
+    /// Byte ranges of every match in text.
+    fn find_ranges(&self, text: &str) -> Vec<Range<usize>>;
+
+    fn is_match(&self, text: &str) -> bool {
+        !self.find_ranges(text).is_empty()
+    }
+}
+
+/// Plain substring search.
+impl SearchPattern for str {
+    fn find_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        text.match_indices(self)
+            .map(|(start, found)| start..start + found.len())
+            .collect()
+    }
+}
+
+impl SearchPattern for String {
+    fn find_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        self.as_str().find_ranges(text)
+    }
+}
+
+#[cfg(feature = "cli-features")]
+impl SearchPattern for Regex {
+    /// Empty matches are kept, like those of ^$ on an empty value.
+    fn find_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        self.find_iter(text).map(|found| found.range()).collect()
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        Regex::is_match(self, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search::*;
+
+    #[test]
+    fn substring_ranges() {
+        assert_eq!(vec![0..3, 8..11], "old".find_ranges("old.com/old"));
+        assert!("".find_ranges("anything").is_empty());
+        assert!(!"new".is_match("old.com"));
+    }
+
+    #[cfg(feature = "cli-features")]
+    #[test]
+    fn regex_ranges() {
+        let regex = Regex::new("o+").unwrap();
+        assert_eq!(vec![1..3, 6..7], regex.find_ranges("hoo.cdo"));
+
+        let empty = Regex::new("^$").unwrap();
+        assert!(SearchPattern::is_match(&empty, ""));
+        assert!(!SearchPattern::is_match(&empty, "value"));
+        assert!(SearchPattern::is_match(&Regex::new("x*").unwrap(), "abc"));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::error::{DBError, KeyError};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use std::convert::TryInto;
+
+type Result<T> = std::result::Result<T, DBError>;
+
+/// A new Ed25519 key pair, the signing key and the public key as hex.
+pub fn generate() -> (String, String) {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    (
+        hex::encode(signing_key.to_bytes()),
+        hex::encode(signing_key.verifying_key().to_bytes()),
+    )
+}
+
+/// Signature of contents as hex, to be stored next to the file.
+pub fn sign(contents: &[u8], signing_key: &str) -> Result<String> {
+    let signing_key = SigningKey::from_bytes(&key_bytes(signing_key)?);
+    Ok(hex::encode(signing_key.sign(contents).to_bytes()))
+}
+
+/// Fails unless signature was made over contents with the key of public_key.
+pub fn verify(contents: &[u8], signature: &str, public_key: &str) -> Result<()> {
+    let public_key =
+        VerifyingKey::from_bytes(&key_bytes(public_key)?).map_err(|_| KeyError::ParseError)?;
+    let signature = hex::decode(signature.trim()).map_err(|_| DBError::SignatureInvalid)?;
+    let signature = Signature::from_slice(&signature).map_err(|_| DBError::SignatureInvalid)?;
+    public_key
+        .verify_strict(contents, &signature)
+        .map_err(|_| DBError::SignatureInvalid)
+}
+
+fn key_bytes(key: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(key.trim()).map_err(|_| KeyError::ParseError)?;
+    Ok(bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| KeyError::ParseError)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::signing::*;
+
+    #[test]
+    fn sign_verify() {
+        let (signing_key, public_key) = generate();
+        let signature = sign(b"db.host\tlocalhost\n", &signing_key).unwrap();
+        assert!(verify(b"db.host\tlocalhost\n", &signature, &public_key).is_ok());
+
+        assert_eq!(
+            DBError::SignatureInvalid,
+            verify(b"db.host\tevil\n", &signature, &public_key)
+                .err()
+                .unwrap()
+        );
+        let (_, other_key) = generate();
+        assert_eq!(
+            DBError::SignatureInvalid,
+            verify(b"db.host\tlocalhost\n", &signature, &other_key)
+                .err()
+                .unwrap()
+        );
+        assert_eq!(
+            DBError::KeyError(KeyError::ParseError),
+            sign(b"", "not a key").err().unwrap()
+        );
+    }
+}